@@ -35,7 +35,7 @@ fn create_test_db(num_entries: usize, offset_filename: &str, db_filename: &str)
         .chunks(NUM_ENTRIES as usize)
         .into_iter()
         .for_each(|chunk| {
-            view.append_batch(&chunk.collect_vec());
+            view.append_batch(&chunk.collect_vec()).unwrap();
         });
 
     view
@@ -57,7 +57,7 @@ fn flume_view_sql_insert_piets_entire_log(c: &mut Criterion) {
                 .chunks(NUM_ENTRIES as usize)
                 .into_iter()
                 .for_each(|chunk| {
-                    view.append_batch(&chunk.collect_vec());
+                    view.append_batch(&chunk.collect_vec()).unwrap();
                 });
         })
     });
@@ -89,7 +89,7 @@ fn flume_view_sql_insert_piets_entire_log_with_decryption(c: &mut Criterion) {
                     .chunks(NUM_ENTRIES as usize)
                     .into_iter()
                     .for_each(|chunk| {
-                        view.append_batch(&chunk.collect_vec());
+                        view.append_batch(&chunk.collect_vec()).unwrap();
                     });
             })
         },
@@ -116,7 +116,7 @@ fn flume_view_sql_insert(c: &mut Criterion) {
                 .chunks(NUM_ENTRIES as usize)
                 .into_iter()
                 .for_each(|chunk| {
-                    view.append_batch(&chunk.collect_vec());
+                    view.append_batch(&chunk.collect_vec()).unwrap();
                 });
         })
     });
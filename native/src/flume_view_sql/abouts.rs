@@ -2,6 +2,9 @@ use flume_view_sql::*;
 use rusqlite::types::Null;
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+pub const INSERT_ABOUT: &str =
+    "INSERT INTO abouts_raw (link_from_key_id, link_to_author_id, link_to_key_id) VALUES (?, ?, ?)";
+
 pub fn create_abouts_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating abouts tables");
 
@@ -16,30 +19,35 @@ pub fn create_abouts_tables(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
-pub fn insert_abouts(connection: &Connection, message: &SsbMessage, message_key_id: i64) {
-    if let Value::String(about_key) = &message.value.content["about"] {
-        let mut key;
+pub fn insert_abouts(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    if let Some(about_key) = message.value.content["about"]
+        .as_str()
+        .and_then(normalize_ssb_ref)
+    {
+        let key;
 
         let (link_to_author_id, link_to_key_id): (&ToSql, &ToSql) = match about_key.get(0..1) {
             Some("@") => {
-                key = find_or_create_author(connection, about_key).unwrap();
+                key = find_or_create_author(connection, &about_key)?;
                 (&key, &Null)
             }
             Some("%") => {
-                key = find_or_create_key(connection, about_key).unwrap();
+                key = find_or_create_key(connection, &about_key)?;
                 (&Null, &key)
             }
             _ => (&Null, &Null),
         };
 
-        let mut insert_abouts_stmt = connection
-            .prepare_cached("INSERT INTO abouts_raw (link_from_key_id, link_to_author_id, link_to_key_id) VALUES (?, ?, ?)")
-            .unwrap();
+        let mut insert_abouts_stmt = connection.prepare_cached(INSERT_ABOUT)?;
 
-        insert_abouts_stmt
-            .execute(&[&message_key_id, link_to_author_id, link_to_key_id])
-            .unwrap();
+        insert_abouts_stmt.execute(&[&message_key_id, link_to_author_id, link_to_key_id])?;
     }
+
+    Ok(())
 }
 
 pub fn create_abouts_indices(connection: &Connection) -> Result<usize, Error> {
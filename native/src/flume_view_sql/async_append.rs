@@ -0,0 +1,31 @@
+use failure::Error;
+use futures::future::Future;
+use futures::FutureExt;
+use std::sync::{Arc, Mutex};
+
+use flume_view_sql::*;
+
+impl FlumeViewSql {
+    /// Runs `append_batch` on tokio's blocking thread pool so it doesn't stall the async runtime,
+    /// and resolves to what `append_batch` returned for each item.
+    ///
+    /// This isn't an `async fn` taking `&mut self`: the crate is on the 2015 edition, which has no
+    /// `async fn`, and `spawn_blocking` needs a `'static` closure, which can't borrow the view.
+    /// So the view is passed in an `Arc<Mutex<_>>` that the blocking task can own a handle to.
+    /// A mutex poisoned by an earlier panic errors with `FlumeViewSqlError::ViewLockPoisoned`.
+    pub fn append_batch_async(
+        view: Arc<Mutex<FlumeViewSql>>,
+        items: Vec<(Sequence, Vec<u8>)>,
+    ) -> impl Future<Output = Result<Vec<AppendResult>, Error>> {
+        tokio::task::spawn_blocking(move || -> Result<Vec<AppendResult>, Error> {
+            let mut view = view
+                .lock()
+                .map_err(|_| FlumeViewSqlError::ViewLockPoisoned {})?;
+            view.append_batch(&items)
+        })
+        .map(|joined| match joined {
+            Ok(result) => result,
+            Err(_) => Err(FlumeViewSqlError::AsyncSpawnFailed {}.into()),
+        })
+    }
+}
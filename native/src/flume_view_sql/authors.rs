@@ -1,16 +1,17 @@
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+pub const SELECT_AUTHOR_ID: &str = "SELECT id FROM authors WHERE author=?1";
+pub const INSERT_AUTHOR: &str = "INSERT OR IGNORE INTO authors (author) VALUES (?)";
+
+//The insert is a no-op when the author already exists, so the select always finds a row.
 pub fn find_or_create_author(connection: &Connection, author: &str) -> Result<i64, Error> {
-    let mut stmt = connection.prepare_cached("SELECT id FROM authors WHERE author=?1")?;
+    connection
+        .prepare_cached(INSERT_AUTHOR)?
+        .execute(&[author])?;
 
-    stmt.query_row(&[author], |row| row.get(0))
-        .or_else(|_| {
-            connection
-                .prepare_cached("INSERT INTO authors (author) VALUES (?)")
-                .map(|mut stmt| stmt.execute(&[author]))
-                .map(|_| connection.last_insert_rowid())
-        })
-        .map_err(|err| err.into())
+    connection
+        .prepare_cached(SELECT_AUTHOR_ID)?
+        .query_row(&[author], |row| row.get(0))
 }
 
 pub fn create_authors_tables(connection: &Connection) -> Result<usize, Error> {
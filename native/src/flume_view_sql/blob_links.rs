@@ -1,6 +1,9 @@
 use flume_view_sql::*;
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+pub const INSERT_BLOB_LINK: &str =
+    "INSERT INTO blob_links_raw (link_from_key_id, link_to_blob_id) VALUES (?, ?)";
+
 pub fn create_blob_links_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating blob_links tables");
 
@@ -36,28 +39,24 @@ pub fn insert_blob_links(
     connection: &Connection,
     links: &[&serde_json::Value],
     message_key_id: i64,
-) {
-    let mut insert_link_stmt = connection
-        .prepare_cached(
-            "INSERT INTO blob_links_raw (link_from_key_id, link_to_blob_id) VALUES (?, ?)",
-        )
-        .unwrap();
+) -> Result<(), Error> {
+    let mut insert_link_stmt = connection.prepare_cached(INSERT_BLOB_LINK)?;
 
     links
         .iter()
-        .filter(|link| link.is_string())
-        .map(|link| link.as_str().unwrap())
+        .filter_map(|link| link.as_str())
+        .filter_map(normalize_ssb_ref)
         .filter(|link| link.starts_with("&"))
-        .map(|link| find_or_create_blob(&connection, link).unwrap())
-        .for_each(|link_id| {
-            insert_link_stmt
-                .execute(&[&message_key_id, &link_id])
-                .unwrap();
-        });
+        .try_for_each(|link| {
+            let link_id = find_or_create_blob(&connection, &link)?;
+            insert_link_stmt.execute(&[&message_key_id, &link_id])?;
+            Ok(())
+        })
 }
 
 pub fn create_blob_links_indices(connection: &Connection) -> Result<usize, Error> {
-    create_blob_links_index(connection)
+    create_blob_links_index(connection)?;
+    create_blob_links_from_index(connection)
 }
 
 fn create_blob_links_index(conn: &Connection) -> Result<usize, Error> {
@@ -68,3 +67,11 @@ fn create_blob_links_index(conn: &Connection) -> Result<usize, Error> {
     )
     .map_err(|err| err.into())
 }
+
+fn create_blob_links_from_index(conn: &Connection) -> Result<usize, Error> {
+    trace!("Creating blob links from index");
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS blob_links_from_index on blob_links_raw (link_from_key_id)",
+        NO_PARAMS,
+    )
+}
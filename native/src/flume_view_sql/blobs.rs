@@ -1,12 +1,15 @@
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+pub const SELECT_BLOB_ID: &str = "SELECT id FROM blobs WHERE blob=?1";
+pub const INSERT_BLOB: &str = "INSERT INTO blobs (blob) VALUES (?)";
+
 pub fn find_or_create_blob(connection: &Connection, blob: &str) -> Result<i64, Error> {
-    let mut stmt = connection.prepare_cached("SELECT id FROM blobs WHERE blob=?1")?;
+    let mut stmt = connection.prepare_cached(SELECT_BLOB_ID)?;
 
     stmt.query_row(&[blob], |row| row.get(0))
         .or_else(|_| {
             connection
-                .prepare_cached("INSERT INTO blobs (blob) VALUES (?)")
+                .prepare_cached(INSERT_BLOB)
                 .map(|mut stmt| stmt.execute(&[blob]))
                 .map(|_| connection.last_insert_rowid())
         })
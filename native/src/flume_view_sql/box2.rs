@@ -0,0 +1,8 @@
+use private_box::SecretKey;
+
+//There's no rust implementation of box2 yet, so nothing decrypts for now. box2 messages are
+//stored like any private message we can't read, raw content included, so they can be decrypted
+//once this is filled in.
+pub fn decrypt_box2(_cypher: &[u8], _secret_keys: &[SecretKey]) -> Option<Vec<u8>> {
+    None
+}
@@ -2,32 +2,30 @@ use flume_view_sql::*;
 use rusqlite::{Connection, Error, NO_PARAMS};
 use serde_json::Value;
 
-pub fn insert_branches(connection: &Connection, message: &SsbMessage, message_key_id: i64) {
+pub const INSERT_BRANCH: &str =
+    "INSERT INTO branches_raw (link_from_key_id, link_to_key_id) VALUES (?, ?)";
+
+pub fn insert_branches(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
     if let Some(branches_value) = message.value.content.get("branch") {
-        let mut insert_branch_stmt = connection
-            .prepare_cached(
-                "INSERT INTO branches_raw (link_from_key_id, link_to_key_id) VALUES (?, ?)",
-            )
-            .unwrap();
+        let mut insert_branch_stmt = connection.prepare_cached(INSERT_BRANCH)?;
 
         let branches = match branches_value {
-            Value::Array(arr) => arr
-                .iter()
-                .map(|value| value.as_str().unwrap().to_string())
-                .collect(),
-            Value::String(branch) => vec![branch.as_str().to_string()],
+            Value::Array(arr) => arr.iter().filter_map(|value| value.as_str()).collect(),
+            Value::String(branch) => vec![branch.as_str()],
             _ => Vec::new(),
         };
 
-        branches
-            .iter()
-            .map(|branch| find_or_create_key(connection, branch).unwrap())
-            .for_each(|link_to_key_id| {
-                insert_branch_stmt
-                    .execute(&[&message_key_id, &link_to_key_id])
-                    .unwrap();
-            })
+        for branch in branches.into_iter().filter_map(normalize_ssb_ref) {
+            let link_to_key_id = find_or_create_key(connection, &branch)?;
+            insert_branch_stmt.execute(&[&message_key_id, &link_to_key_id])?;
+        }
     }
+
+    Ok(())
 }
 
 pub fn create_branches_tables(connection: &Connection) -> Result<usize, Error> {
@@ -43,7 +41,23 @@ pub fn create_branches_tables(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
-pub fn create_branches_indices(_connection: &Connection) -> Result<usize, Error> {
-    trace!("Creating branches tables");
-    Ok(0)
+pub fn create_branches_indices(connection: &Connection) -> Result<usize, Error> {
+    create_branches_from_index(connection)?;
+    create_branches_to_index(connection)
+}
+
+fn create_branches_from_index(conn: &Connection) -> Result<usize, Error> {
+    trace!("Creating branches from index");
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS branches_from_index on branches_raw (link_from_key_id)",
+        NO_PARAMS,
+    )
+}
+
+fn create_branches_to_index(conn: &Connection) -> Result<usize, Error> {
+    trace!("Creating branches to index");
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS branches_to_index on branches_raw (link_to_key_id)",
+        NO_PARAMS,
+    )
 }
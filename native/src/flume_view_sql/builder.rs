@@ -0,0 +1,403 @@
+use failure::Error;
+use private_box::SecretKey;
+use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+
+use flume_view_sql::*;
+
+const IN_MEMORY_PATH: &str = ":memory:";
+const DEFAULT_CACHE_SIZE_KB: u32 = 64 * 1024;
+
+//The journal modes sqlite knows. Anything else would be interpolated straight into a pragma.
+const JOURNAL_MODES: [&str; 6] = ["WAL", "DELETE", "TRUNCATE", "PERSIST", "MEMORY", "OFF"];
+//Room for every warmed statement with space left over for queries.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// sqlite's `PRAGMA synchronous` setting. `Off` is fastest and fine for bulk loads that can be
+/// redone from the log, but a crash or power loss can corrupt the db.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SynchronousMode {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl SynchronousMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SynchronousMode::Off => "OFF",
+            SynchronousMode::Normal => "NORMAL",
+            SynchronousMode::Full => "FULL",
+            SynchronousMode::Extra => "EXTRA",
+        }
+    }
+}
+
+/// sqlite's `PRAGMA auto_vacuum` setting. It only takes effect on a new db, before any tables
+/// are created. `Incremental` keeps freed pages for reuse until `PRAGMA incremental_vacuum`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoVacuumMode {
+    None,
+    Full,
+    Incremental,
+}
+
+impl AutoVacuumMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AutoVacuumMode::None => "NONE",
+            AutoVacuumMode::Full => "FULL",
+            AutoVacuumMode::Incremental => "INCREMENTAL",
+        }
+    }
+}
+
+/// sqlite's `PRAGMA temp_store` setting, where temporary tables and indices go. `Memory` uses
+/// more RAM but speeds up GROUP BY and ORDER BY over large result sets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TempStoreMode {
+    Default,
+    File,
+    Memory,
+}
+
+impl TempStoreMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TempStoreMode::Default => "DEFAULT",
+            TempStoreMode::File => "FILE",
+            TempStoreMode::Memory => "MEMORY",
+        }
+    }
+}
+
+pub struct FlumeViewSqlBuilder {
+    path: String,
+    secret_keys: Vec<SecretKey>,
+    pub_key: String,
+    page_size: u32,
+    synchronous: SynchronousMode,
+    auto_vacuum: AutoVacuumMode,
+    temp_store: TempStoreMode,
+    journal_mode: String,
+    cache_size_kb: u32,
+    mmap_size_bytes: Option<i64>,
+    existing_only: bool,
+    uri: bool,
+    batch_chunk_size: Option<usize>,
+    max_message_size: Option<usize>,
+    content_type_allowlist: Option<Vec<String>>,
+    defer_index_creation: bool,
+    key_filter: bool,
+}
+
+impl Default for FlumeViewSqlBuilder {
+    fn default() -> FlumeViewSqlBuilder {
+        FlumeViewSqlBuilder {
+            path: String::new(),
+            secret_keys: Vec::new(),
+            pub_key: String::new(),
+            page_size: 4096,
+            synchronous: SynchronousMode::Normal,
+            auto_vacuum: AutoVacuumMode::Incremental,
+            temp_store: TempStoreMode::Memory,
+            journal_mode: "WAL".to_string(),
+            cache_size_kb: DEFAULT_CACHE_SIZE_KB,
+            mmap_size_bytes: None,
+            existing_only: false,
+            uri: false,
+            batch_chunk_size: None,
+            max_message_size: Some(DEFAULT_MAX_MESSAGE_SIZE),
+            content_type_allowlist: None,
+            defer_index_creation: false,
+            key_filter: false,
+        }
+    }
+}
+
+impl FlumeViewSqlBuilder {
+    pub fn new() -> FlumeViewSqlBuilder {
+        FlumeViewSqlBuilder::default()
+    }
+
+    pub fn path(mut self, path: &str) -> FlumeViewSqlBuilder {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Keep the whole database in memory. Nothing is written to disk.
+    pub fn in_memory(mut self) -> FlumeViewSqlBuilder {
+        self.path = IN_MEMORY_PATH.to_string();
+        self.journal_mode = "MEMORY".to_string();
+        self
+    }
+
+    /// Open an sqlite `file:` URI rather than a plain path, so URI parameters like
+    /// `cache=shared` can be used.
+    pub fn uri(mut self, uri: &str) -> FlumeViewSqlBuilder {
+        self.path = uri.to_string();
+        self.uri = true;
+        if is_in_memory_uri(uri) {
+            self.journal_mode = "MEMORY".to_string();
+        }
+        self
+    }
+
+    pub fn keys(mut self, secret_keys: Vec<SecretKey>) -> FlumeViewSqlBuilder {
+        self.secret_keys = secret_keys;
+        self
+    }
+
+    pub fn pub_key(mut self, pub_key: &str) -> FlumeViewSqlBuilder {
+        self.pub_key = pub_key.to_string();
+        self
+    }
+
+    pub fn page_size(mut self, page_size: u32) -> FlumeViewSqlBuilder {
+        self.page_size = page_size;
+        self
+    }
+
+    pub fn synchronous(mut self, mode: SynchronousMode) -> FlumeViewSqlBuilder {
+        self.synchronous = mode;
+        self
+    }
+
+    pub fn auto_vacuum(mut self, mode: AutoVacuumMode) -> FlumeViewSqlBuilder {
+        self.auto_vacuum = mode;
+        self
+    }
+
+    pub fn temp_store(mut self, mode: TempStoreMode) -> FlumeViewSqlBuilder {
+        self.temp_store = mode;
+        self
+    }
+
+    /// One of sqlite's journal modes, eg. `WAL` or `DELETE`. `build` rejects anything else with
+    /// `FlumeViewSqlError::InvalidJournalMode`.
+    pub fn journal_mode(mut self, mode: &str) -> FlumeViewSqlBuilder {
+        self.journal_mode = mode.to_string();
+        self
+    }
+
+    /// Size of sqlite's page cache in kibibytes. `build` rejects 0 with
+    /// `FlumeViewSqlError::InvalidCacheSize`.
+    pub fn cache_size(mut self, kb: u32) -> FlumeViewSqlBuilder {
+        self.cache_size_kb = kb;
+        self
+    }
+
+    /// Read up to `bytes` of the db through memory-mapped I/O, which speeds up reads of large
+    /// dbs. Off by default. Needs an OS that supports mmap, and sqlite quietly caps the size at
+    /// its compile time limit.
+    pub fn mmap_size(mut self, bytes: i64) -> FlumeViewSqlBuilder {
+        self.mmap_size_bytes = Some(bytes);
+        self
+    }
+
+    /// Split `append_batch` calls into transactions of at most `size` items, so a huge batch
+    /// doesn't build one enormous transaction.
+    pub fn batch_chunk_size(mut self, size: usize) -> FlumeViewSqlBuilder {
+        self.batch_chunk_size = Some(size);
+        self
+    }
+
+    /// Largest item in bytes that appends will parse, 1 MiB by default. None removes the limit.
+    pub fn max_message_size(mut self, limit: Option<usize>) -> FlumeViewSqlBuilder {
+        self.max_message_size = limit;
+        self
+    }
+
+    /// Only store public messages with one of these content types, other types error with
+    /// `FlumeViewSqlError::DisallowedContentType`. Private messages are always stored.
+    pub fn content_type_allowlist(mut self, types: Vec<String>) -> FlumeViewSqlBuilder {
+        self.content_type_allowlist = Some(types);
+        self
+    }
+
+    /// Don't index a new db when it's created, which makes a bulk import faster. Call
+    /// `FlumeViewSql::finalize_import` once the import is done to create the indices.
+    pub fn defer_index_creation(mut self, defer: bool) -> FlumeViewSqlBuilder {
+        self.defer_index_creation = defer;
+        self
+    }
+
+    /// Keep a bloom filter of stored keys for `FlumeViewSql::key_probably_exists`. Building it
+    /// reads every key in the db, so opening a large db is slower with it on.
+    pub fn key_filter(mut self, enabled: bool) -> FlumeViewSqlBuilder {
+        self.key_filter = enabled;
+        self
+    }
+
+    /// Only open a db that already exists with a schema this build understands. Errors with
+    /// `FlumeViewSqlError::UnrecognizedSchema` instead of deleting and rebuilding it.
+    pub fn existing_only(mut self) -> FlumeViewSqlBuilder {
+        self.existing_only = true;
+        self
+    }
+
+    pub fn build(self) -> Result<FlumeViewSql, Error> {
+        self.check_options()?;
+
+        let extra_flags = if self.uri {
+            OpenFlags::SQLITE_OPEN_URI
+        } else {
+            OpenFlags::empty()
+        };
+        let mut connection = if self.existing_only {
+            open_existing_connection(&self.path, extra_flags)?
+        } else {
+            create_connection(&self.path, extra_flags)?
+        };
+        let needs_rebuild = !is_db_up_to_date(&connection)?;
+
+        if self.existing_only {
+            check_schema_is_known(&connection, needs_rebuild)?;
+        }
+
+        //A fresh in-memory db has no file to delete, and a URI isn't a path that can be deleted so
+        //its tables are dropped instead.
+        if needs_rebuild && self.uri {
+            info!("sqlite db is out of date. Dropping its tables and it will be rebuilt.");
+            drop_schema(&connection)?;
+        } else if needs_rebuild && self.path != IN_MEMORY_PATH {
+            info!("sqlite db is out of date. Deleting db and it will be rebuilt.");
+            std::fs::remove_file(&self.path)?;
+
+            connection = create_connection(&self.path, extra_flags)?;
+        }
+
+        //The page size and auto_vacuum have to be set before any tables are created for it to take effect.
+        self.set_pragmas(&connection)?;
+
+        if needs_rebuild {
+            create_tables(&connection)?;
+            if !self.defer_index_creation {
+                create_indices(&connection)?;
+            }
+            create_views(&connection)?;
+
+            set_db_version(&connection)?;
+            set_schema_version(&connection, CURRENT_SCHEMA_VERSION)?;
+            set_author_that_is_me(&connection, &self.pub_key)?;
+        }
+
+        let tx = connection.transaction()?;
+        run_migrations(&tx)?;
+        #[cfg(feature = "fts")]
+        create_fts_tables(&tx)?;
+        tx.commit()?;
+
+        connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        let bloom = if self.key_filter {
+            Some(load_key_filter(&connection)?)
+        } else {
+            None
+        };
+
+        let view = FlumeViewSql {
+            connection,
+            secret_keys: self.secret_keys,
+            read_only: false,
+            batch_chunk_size: self.batch_chunk_size,
+            max_message_size: self.max_message_size,
+            content_type_allowlist: self.content_type_allowlist,
+            bloom,
+        };
+        view.warm_statement_cache()?;
+
+        Ok(view)
+    }
+
+    //Both of these end up formatted into pragmas, so check them before touching the db.
+    fn check_options(&self) -> Result<(), Error> {
+        let journal_mode_is_known = JOURNAL_MODES
+            .iter()
+            .any(|mode| mode.eq_ignore_ascii_case(&self.journal_mode));
+        if !journal_mode_is_known {
+            return Err(FlumeViewSqlError::InvalidJournalMode {
+                mode: self.journal_mode.clone(),
+            }
+            .into());
+        }
+
+        if self.cache_size_kb == 0 {
+            return Err(FlumeViewSqlError::InvalidCacheSize {}.into());
+        }
+
+        Ok(())
+    }
+
+    fn set_pragmas(&self, connection: &Connection) -> Result<(), Error> {
+        connection.execute(
+            &format!("PRAGMA synchronous = {}", self.synchronous.as_str()),
+            NO_PARAMS,
+        )?;
+        connection.execute(&format!("PRAGMA page_size = {}", self.page_size), NO_PARAMS)?;
+        connection.execute(
+            &format!("PRAGMA auto_vacuum = {}", self.auto_vacuum.as_str()),
+            NO_PARAMS,
+        )?;
+        connection.execute(
+            &format!("PRAGMA temp_store = {}", self.temp_store.as_str()),
+            NO_PARAMS,
+        )?;
+
+        set_cache_size(connection, self.cache_size_kb)?;
+
+        //Unlike the pragmas above, setting mmap_size returns the new size as a row.
+        if let Some(bytes) = self.mmap_size_bytes {
+            connection.query_row::<i64, _, _>(
+                &format!("PRAGMA mmap_size = {}", bytes),
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
+        }
+
+        let journal_mode: String = connection.query_row(
+            &format!("PRAGMA journal_mode = {}", self.journal_mode),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+
+        if !journal_mode.eq_ignore_ascii_case(&self.journal_mode) {
+            return Err(FlumeViewSqlError::JournalModeNotSet {
+                requested: self.journal_mode.clone(),
+                actual: journal_mode,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+fn open_existing_connection(path: &str, extra_flags: OpenFlags) -> Result<Connection, Error> {
+    let flags: OpenFlags =
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX | extra_flags;
+
+    Connection::open_with_flags(path, flags).map_err(|err| err.into())
+}
+
+//Older schema versions are fine, run_migrations brings them up to date.
+fn check_schema_is_known(connection: &Connection, needs_rebuild: bool) -> Result<(), Error> {
+    //A db without a schema_version table was never set up by us.
+    let found = get_schema_version(connection).unwrap_or(0);
+
+    if needs_rebuild || found > CURRENT_SCHEMA_VERSION {
+        return Err(FlumeViewSqlError::UnrecognizedSchema {
+            found,
+            expected: CURRENT_SCHEMA_VERSION,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn set_cache_size(connection: &Connection, kb: u32) -> Result<usize, Error> {
+    //A negative cache_size is interpreted by sqlite as a size in kibibytes.
+    connection
+        .execute(&format!("PRAGMA cache_size = -{}", kb), NO_PARAMS)
+        .map_err(|err| err.into())
+}
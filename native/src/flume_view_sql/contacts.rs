@@ -2,12 +2,16 @@ use flume_view_sql::*;
 use rusqlite::{Connection, Error, NO_PARAMS};
 use serde_json::Value;
 
+pub const REPLACE_CONTACT: &str =
+    "REPLACE INTO contacts_raw (link_from_key_id, author_id, contact_author_id, state, is_decrypted) VALUES (?, ?, ?, ?, ?)";
+
 pub fn create_contacts_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating contacts tables");
     connection.execute(
         "
     CREATE TABLE IF NOT EXISTS contacts_raw(
         id INTEGER PRIMARY KEY,
+        link_from_key_id INTEGER,
         author_id INTEGER,
         contact_author_id INTEGER,
         is_decrypted BOOLEAN,
@@ -21,14 +25,28 @@ pub fn create_contacts_tables(connection: &Connection) -> Result<usize, Error> {
 pub fn insert_or_update_contacts(
     connection: &Connection,
     message: &SsbMessage,
-    _message_key_id: i64,
+    message_key_id: i64,
     is_decrypted: bool,
-) {
-    if let Value::String(contact) = &message.value.content["contact"] {
-        let is_blocking = message.value.content["blocking"].as_bool().unwrap_or(false);
-        let is_following = message.value.content["following"]
-            .as_bool()
-            .unwrap_or(false);
+) -> Result<(), Error> {
+    insert_contacts_from_content(
+        connection,
+        &message.value.author,
+        &message.value.content,
+        message_key_id,
+        is_decrypted,
+    )
+}
+
+fn insert_contacts_from_content(
+    connection: &Connection,
+    author: &str,
+    content: &Value,
+    message_key_id: i64,
+    is_decrypted: bool,
+) -> Result<(), Error> {
+    if let Some(contact) = content["contact"].as_str().and_then(normalize_ssb_ref) {
+        let is_blocking = content["blocking"].as_bool().unwrap_or(false);
+        let is_following = content["following"].as_bool().unwrap_or(false);
         let state = if is_blocking {
             -1
         } else if is_following {
@@ -37,21 +55,53 @@ pub fn insert_or_update_contacts(
             0
         };
 
-        let author_id = find_or_create_author(&connection, &message.value.author).unwrap();
-        let mut insert_contacts_stmt = connection
-            .prepare_cached("REPLACE INTO contacts_raw (author_id, contact_author_id, state, is_decrypted) VALUES (?, ?, ?, ?)")
-            .unwrap();
-        let contact_author_id = find_or_create_author(&connection, contact).unwrap();
+        let author_id = find_or_create_author(&connection, author)?;
+        let mut insert_contacts_stmt = connection.prepare_cached(REPLACE_CONTACT)?;
+        let contact_author_id = find_or_create_author(&connection, &contact)?;
+
+        insert_contacts_stmt.execute(&[
+            &message_key_id,
+            &author_id,
+            &contact_author_id,
+            &state,
+            &is_decrypted as &ToSql,
+        ])?;
+    }
+
+    Ok(())
+}
 
-        insert_contacts_stmt
-            .execute(&[
-                &author_id,
-                &contact_author_id,
-                &state,
-                &is_decrypted as &ToSql,
-            ])
-            .unwrap();
+//Older dbs didn't record which message a contact row came from, so truncating the log couldn't
+//remove it. The table is rebuilt from the content of the stored messages.
+pub fn add_contacts_link_from_column(connection: &Connection) -> Result<usize, Error> {
+    trace!("Adding link_from_key_id column to contacts");
+    if !column_exists(connection, "contacts_raw", "link_from_key_id")? {
+        connection.execute(
+            "ALTER TABLE contacts_raw ADD COLUMN link_from_key_id INTEGER",
+            NO_PARAMS,
+        )?;
     }
+    connection.execute("DELETE FROM contacts_raw", NO_PARAMS)?;
+
+    let messages = connection
+        .prepare(
+            "SELECT key_id, authors.author, content, is_decrypted FROM messages_raw
+            JOIN authors ON authors.id = messages_raw.author_id
+            WHERE content LIKE '%\"contact\"%'
+            ORDER BY flume_seq",
+        )?
+        .query_map(NO_PARAMS, |row| {
+            (row.get(0), row.get(1), row.get(2), row.get(3))
+        })?
+        .collect::<Result<Vec<(i64, String, String, bool)>, _>>()?;
+
+    for (key_id, author, content, is_decrypted) in &messages {
+        if let Ok(content) = serde_json::from_str::<Value>(content) {
+            insert_contacts_from_content(connection, author, &content, *key_id, *is_decrypted)?;
+        }
+    }
+
+    Ok(messages.len())
 }
 
 pub fn create_contacts_indices(connection: &Connection) -> Result<usize, Error> {
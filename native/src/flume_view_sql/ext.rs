@@ -0,0 +1,324 @@
+use failure::Error;
+use flumedb::Sequence;
+use serde_json::Value;
+use ssb_types::SsbMessage;
+use std::collections::HashMap;
+
+use flume_view_sql::{DbStats, FlumeViewSql};
+
+/// The read side of `FlumeViewSql` as a trait, so it can be used through a `Box<FlumeViewSql>`
+/// or from code generic over the view. Every method just calls the inherent method of the same
+/// name, so anything that can lend out a `FlumeViewSql` gets the trait from the impl below.
+pub trait FlumeViewSqlExt: AsRef<FlumeViewSql> + AsMut<FlumeViewSql> {
+    fn get_seq_by_key(&mut self, key: &str) -> Result<i64, Error> {
+        self.as_mut().get_seq_by_key(key)
+    }
+
+    fn get_seqs_by_keys(&mut self, keys: &[&str]) -> Result<HashMap<String, i64>, Error> {
+        self.as_mut().get_seqs_by_keys(keys)
+    }
+
+    fn get_message_by_key(&mut self, key: &str) -> Result<Option<SsbMessage>, Error> {
+        self.as_mut().get_message_by_key(key)
+    }
+
+    fn get_message_json(&mut self, key: &str) -> Result<Option<String>, Error> {
+        self.as_mut().get_message_json(key)
+    }
+
+    fn get_previous_key(&mut self, key: &str) -> Result<Option<String>, Error> {
+        self.as_mut().get_previous_key(key)
+    }
+
+    fn get_raw_content(&mut self, key: &str) -> Result<Option<String>, Error> {
+        self.as_mut().get_raw_content(key)
+    }
+
+    fn get_decrypted_messages(&mut self, limit: i64, offset: i64) -> Result<Vec<String>, Error> {
+        self.as_mut().get_decrypted_messages(limit, offset)
+    }
+
+    fn get_encrypted_failed_messages(
+        &mut self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<String>, Error> {
+        self.as_mut().get_encrypted_failed_messages(limit, offset)
+    }
+
+    fn get_private_message_keys(
+        &mut self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<(String, bool)>, Error> {
+        self.as_mut().get_private_message_keys(limit, offset)
+    }
+
+    fn get_decryption_failures(&mut self) -> Result<Vec<(String, String)>, Error> {
+        self.as_mut().get_decryption_failures()
+    }
+
+    fn get_seqs_by_type(&mut self, content_type: &str) -> Result<Vec<i64>, Error> {
+        self.as_mut().get_seqs_by_type(content_type)
+    }
+
+    fn get_seqs_by_type_paged(
+        &mut self,
+        content_type: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<i64>, Error> {
+        self.as_mut()
+            .get_seqs_by_type_paged(content_type, limit, offset)
+    }
+
+    fn get_all_content_types(&mut self) -> Result<Vec<String>, Error> {
+        self.as_mut().get_all_content_types()
+    }
+
+    fn get_count_by_type(&mut self, content_type: &str) -> Result<i64, Error> {
+        self.as_mut().get_count_by_type(content_type)
+    }
+
+    fn get_total_message_count(&mut self) -> Result<i64, Error> {
+        self.as_mut().get_total_message_count()
+    }
+
+    fn get_messages_by_channel(&mut self, channel: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_messages_by_channel(channel)
+    }
+
+    fn get_all_channels(&mut self) -> Result<Vec<String>, Error> {
+        self.as_mut().get_all_channels()
+    }
+
+    fn get_seqs_by_author(&mut self, author: &str) -> Result<Vec<i64>, Error> {
+        self.as_mut().get_seqs_by_author(author)
+    }
+
+    fn get_feed_for_author(&mut self, author: &str) -> Result<Vec<SsbMessage>, Error> {
+        self.as_mut().get_feed_for_author(author)
+    }
+
+    fn get_seqs_by_type_and_author(
+        &mut self,
+        content_type: &str,
+        author: &str,
+    ) -> Result<Vec<i64>, Error> {
+        self.as_mut()
+            .get_seqs_by_type_and_author(content_type, author)
+    }
+
+    fn get_messages_by_author(
+        &mut self,
+        author: &str,
+        database: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        self.as_mut().get_messages_by_author(author, database)
+    }
+
+    fn get_authors_list(&mut self) -> Result<Vec<String>, Error> {
+        self.as_mut().get_authors_list()
+    }
+
+    fn get_contacts_for_author(
+        &mut self,
+        author: &str,
+    ) -> Result<Vec<(String, bool, bool)>, Error> {
+        self.as_mut().get_contacts_for_author(author)
+    }
+
+    fn get_follows_for_author(&mut self, author: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_follows_for_author(author)
+    }
+
+    fn get_followers_for_author(&mut self, author: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_followers_for_author(author)
+    }
+
+    fn get_social_graph(&mut self) -> Result<HashMap<String, Vec<(String, bool)>>, Error> {
+        self.as_mut().get_social_graph()
+    }
+
+    fn get_latest_about_for_author(&mut self, author: &str) -> Result<Option<Value>, Error> {
+        self.as_mut().get_latest_about_for_author(author)
+    }
+
+    fn get_message_count_per_author(&mut self) -> Result<HashMap<String, i64>, Error> {
+        self.as_mut().get_message_count_per_author()
+    }
+
+    fn get_latest_sequence_for_author(&mut self, author: &str) -> Result<Option<u32>, Error> {
+        self.as_mut().get_latest_sequence_for_author(author)
+    }
+
+    fn get_links_from_message(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_links_from_message(key)
+    }
+
+    fn get_links_to_message(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_links_to_message(key)
+    }
+
+    fn get_link_graph_for_author(&mut self, author: &str) -> Result<Vec<(String, String)>, Error> {
+        self.as_mut().get_link_graph_for_author(author)
+    }
+
+    fn get_hub_messages(
+        &mut self,
+        min_unique_authors: i64,
+        limit: i64,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        self.as_mut().get_hub_messages(min_unique_authors, limit)
+    }
+
+    fn get_most_linked_messages(&mut self, limit: i64) -> Result<Vec<(String, i64)>, Error> {
+        self.as_mut().get_most_linked_messages(limit)
+    }
+
+    fn get_dangling_links(&mut self) -> Result<Vec<String>, Error> {
+        self.as_mut().get_dangling_links()
+    }
+
+    fn get_blobs_referenced_by_message(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_blobs_referenced_by_message(key)
+    }
+
+    fn get_vote_count_for_message(&mut self, key: &str) -> Result<i64, Error> {
+        self.as_mut().get_vote_count_for_message(key)
+    }
+
+    fn get_votes_breakdown_for_message(&mut self, key: &str) -> Result<HashMap<i64, i64>, Error> {
+        self.as_mut().get_votes_breakdown_for_message(key)
+    }
+
+    fn get_mentions_of(&mut self, target: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_mentions_of(target)
+    }
+
+    fn get_messages_referencing_author(&mut self, author: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_messages_referencing_author(author)
+    }
+
+    fn check_feed_integrity(&mut self, author: &str) -> Result<(), Error> {
+        self.as_mut().check_feed_integrity(author)
+    }
+
+    fn get_sequence_gaps_for_author(&mut self, author: &str) -> Result<Vec<(u32, u32)>, Error> {
+        self.as_mut().get_sequence_gaps_for_author(author)
+    }
+
+    fn get_root_messages(
+        &mut self,
+        content_type: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<String>, Error> {
+        self.as_mut().get_root_messages(content_type, limit, offset)
+    }
+
+    fn get_thread_by_root(&mut self, root: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_thread_by_root(root)
+    }
+
+    fn get_thread_participant_count(&mut self, root: &str) -> Result<i64, Error> {
+        self.as_mut().get_thread_participant_count(root)
+    }
+
+    fn get_forks_for_root(&mut self, root: &str) -> Result<Vec<(String, String)>, Error> {
+        self.as_mut().get_forks_for_root(root)
+    }
+
+    fn get_replies_to(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        self.as_mut().get_replies_to(key)
+    }
+
+    fn get_causal_ancestors(&mut self, key: &str, max_depth: u32) -> Result<Vec<String>, Error> {
+        self.as_mut().get_causal_ancestors(key, max_depth)
+    }
+
+    fn get_messages_since_received_time(
+        &mut self,
+        since: f64,
+        limit: i64,
+    ) -> Result<Vec<String>, Error> {
+        self.as_mut().get_messages_since_received_time(since, limit)
+    }
+
+    fn get_recent_messages(&mut self, limit: i64) -> Result<Vec<String>, Error> {
+        self.as_mut().get_recent_messages(limit)
+    }
+
+    fn get_messages_by_time_range(
+        &mut self,
+        start_ts: f64,
+        end_ts: f64,
+        limit: i64,
+    ) -> Result<Vec<String>, Error> {
+        self.as_mut()
+            .get_messages_by_time_range(start_ts, end_ts, limit)
+    }
+
+    fn get_message_count_in_time_range(
+        &mut self,
+        start_ts: f64,
+        end_ts: f64,
+    ) -> Result<i64, Error> {
+        self.as_mut()
+            .get_message_count_in_time_range(start_ts, end_ts)
+    }
+
+    fn get_oldest_message(&mut self) -> Result<Option<String>, Error> {
+        self.as_mut().get_oldest_message()
+    }
+
+    fn get_newest_message(&mut self) -> Result<Option<String>, Error> {
+        self.as_mut().get_newest_message()
+    }
+
+    fn get_current_heads(&mut self, root: &str) -> Result<Vec<i64>, Error> {
+        self.as_mut().get_current_heads(root)
+    }
+
+    fn get_messages_in_flume_seq_range(
+        &mut self,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<(i64, String)>, Error> {
+        self.as_mut().get_messages_in_flume_seq_range(start, end)
+    }
+
+    fn get_stats(&mut self) -> Result<DbStats, Error> {
+        self.as_mut().get_stats()
+    }
+
+    fn check_db_integrity(&mut self) -> Result<(), Error> {
+        self.as_mut().check_db_integrity()
+    }
+
+    fn check_integrity_detailed(&mut self) -> Result<Vec<String>, Error> {
+        self.as_mut().check_integrity_detailed()
+    }
+
+    fn get_latest(&self) -> Result<Sequence, Error> {
+        self.as_ref().get_latest()
+    }
+
+    fn get_user_version(&self) -> Result<i32, Error> {
+        self.as_ref().get_user_version()
+    }
+}
+
+impl<T: AsRef<FlumeViewSql> + AsMut<FlumeViewSql>> FlumeViewSqlExt for T {}
+
+impl AsRef<FlumeViewSql> for FlumeViewSql {
+    fn as_ref(&self) -> &FlumeViewSql {
+        self
+    }
+}
+
+impl AsMut<FlumeViewSql> for FlumeViewSql {
+    fn as_mut(&mut self) -> &mut FlumeViewSql {
+        self
+    }
+}
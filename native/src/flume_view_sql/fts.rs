@@ -0,0 +1,40 @@
+use flume_view_sql::*;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+pub const INSERT_FTS: &str = "INSERT INTO messages_fts (rowid, text) VALUES (?, ?)";
+
+pub fn create_fts_tables(connection: &Connection) -> Result<usize, Error> {
+    let exists: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='messages_fts'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    if exists > 0 {
+        return Ok(0);
+    }
+
+    trace!("Creating full text search tables");
+    connection.execute(
+        "CREATE VIRTUAL TABLE messages_fts USING fts5(text)",
+        NO_PARAMS,
+    )?;
+
+    //Index anything that was appended before full text search was turned on.
+    connection.execute(
+        "INSERT INTO messages_fts (rowid, text)
+        SELECT flume_seq, json_extract(content, '$.text') FROM messages_raw
+        WHERE json_type(content, '$.text') = 'text'",
+        NO_PARAMS,
+    )
+}
+
+pub fn insert_fts(connection: &Connection, message: &SsbMessage, seq: i64) -> Result<(), Error> {
+    if let Some(text) = message.value.content["text"].as_str() {
+        let mut insert_fts_stmt = connection.prepare_cached(INSERT_FTS)?;
+
+        insert_fts_stmt.execute(&[&seq as &ToSql, &text])?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use flume_view_sql::*;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+pub const DELETE_BUILT_ON_HEADS: &str =
+    "DELETE FROM heads_raw WHERE root_id = ?1 AND key_id IN (SELECT link_to_key_id FROM branches_raw WHERE link_from_key_id = ?2)";
+pub const INSERT_HEAD: &str =
+    "INSERT INTO heads_raw (root_id, key_id) SELECT ?1, ?2 WHERE NOT EXISTS (SELECT 1 FROM branches_raw WHERE link_to_key_id = ?2)";
+
+pub fn create_heads_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating heads tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS heads_raw (
+          id INTEGER PRIMARY KEY,
+          root_id INTEGER,
+          key_id INTEGER UNIQUE
+        )",
+        NO_PARAMS,
+    )
+}
+
+//Must run after the message's branches have been inserted.
+pub fn insert_heads(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    if let Some(root) = message.value.content["root"]
+        .as_str()
+        .and_then(normalize_ssb_ref)
+    {
+        let root_id = find_or_create_key(connection, &root)?;
+
+        //Anything this message builds on is no longer a tip of the thread.
+        let mut delete_heads_stmt = connection.prepare_cached(DELETE_BUILT_ON_HEADS)?;
+        delete_heads_stmt.execute(&[&root_id, &message_key_id])?;
+
+        //A message that arrives after one that already builds on it is not a head.
+        let mut insert_head_stmt = connection.prepare_cached(INSERT_HEAD)?;
+        insert_head_stmt.execute(&[&root_id, &message_key_id])?;
+    }
+
+    Ok(())
+}
+
+pub fn create_heads_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating heads index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS heads_root_id_index on heads_raw (root_id)",
+        NO_PARAMS,
+    )
+}
@@ -0,0 +1,113 @@
+use failure::Error;
+use rusqlite::types::{ToSql, ToSqlOutput, Value};
+use rusqlite::Connection;
+use std::vec::IntoIter;
+
+//How many rows are read from sqlite at a time.
+pub const ITER_PAGE_SIZE: i64 = 1000;
+
+//Rows borrow the statement they come from, so an iterator can't own a live cursor. Instead it
+//steps through the query a page at a time with a cached statement, holding only one page in memory.
+//Each page starts after the last flume_seq seen rather than at an offset, so fetching a page is an
+//index seek, and rows written between pages can't make it skip or repeat a key.
+pub struct MessagesIter<'a> {
+    connection: &'a Connection,
+    sql: String,
+    params: Vec<Value>,
+    last_seq: i64,
+    page: IntoIter<(String, i64)>,
+    done: bool,
+}
+
+impl<'a> MessagesIter<'a> {
+    pub fn new(
+        connection: &'a Connection,
+        sql: &str,
+        params: &[&ToSql],
+    ) -> Result<MessagesIter<'a>, Error> {
+        //The iterator outlives the caller's params, so keep owned copies of them.
+        let params = params
+            .iter()
+            .map(|param| {
+                param.to_sql().map(|output| match output {
+                    ToSqlOutput::Borrowed(value) => value.into(),
+                    ToSqlOutput::Owned(value) => value,
+                })
+            })
+            .collect::<Result<Vec<Value>, _>>()?;
+
+        //`sql` selects the key then flume_seq.
+        let sql = format!(
+            "SELECT * FROM ({}) WHERE flume_seq > ?{} ORDER BY flume_seq LIMIT ?{}",
+            sql,
+            params.len() + 1,
+            params.len() + 2
+        );
+
+        Ok(MessagesIter {
+            connection,
+            sql,
+            params,
+            last_seq: std::i64::MIN,
+            page: Vec::new().into_iter(),
+            done: false,
+        })
+    }
+
+    fn next_page(&self) -> Result<Vec<(String, i64)>, Error> {
+        let mut stmt = self.connection.prepare_cached(&self.sql)?;
+
+        let mut params = self
+            .params
+            .iter()
+            .map(|param| param as &ToSql)
+            .collect::<Vec<_>>();
+        params.push(&self.last_seq);
+        params.push(&ITER_PAGE_SIZE);
+
+        let rows = stmt.query_map(&params, |row| (row.get(0), row.get(1)))?;
+
+        rows.collect::<Result<Vec<(String, i64)>, _>>()
+            .map_err(|err| err.into())
+    }
+}
+
+impl<'a> Iterator for MessagesIter<'a> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Result<String, Error>> {
+        if let Some((key, seq)) = self.page.next() {
+            self.last_seq = seq;
+            return Some(Ok(key));
+        }
+
+        if self.done {
+            return None;
+        }
+
+        match self.next_page() {
+            Ok(page) => {
+                self.done = (page.len() as i64) < ITER_PAGE_SIZE;
+                self.page = page.into_iter();
+                self.page.next().map(|(key, seq)| {
+                    self.last_seq = seq;
+                    Ok(key)
+                })
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.page.len();
+
+        if self.done {
+            (buffered, Some(buffered))
+        } else {
+            (buffered, None)
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+pub const SELECT_KEY_ID: &str = "SELECT id FROM keys WHERE key=?1";
+pub const INSERT_KEY: &str = "INSERT INTO keys (key) VALUES (?)";
+
 pub fn find_or_create_key(connection: &Connection, key: &str) -> Result<i64, Error> {
-    let mut stmt = connection.prepare_cached("SELECT id FROM keys WHERE key=?1")?;
+    let mut stmt = connection.prepare_cached(SELECT_KEY_ID)?;
 
     stmt.query_row(&[key], |row| row.get(0))
         .or_else(|_| {
             connection
-                .prepare_cached("INSERT INTO keys (key) VALUES (?)")
+                .prepare_cached(INSERT_KEY)
                 .map(|mut stmt| stmt.execute(&[key]))
                 .map(|_| connection.last_insert_rowid())
         })
@@ -1,6 +1,9 @@
 use flume_view_sql::*;
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+pub const INSERT_LINK: &str =
+    "INSERT INTO links_raw (link_from_key_id, link_to_key_id) VALUES (?, ?)";
+
 pub fn create_links_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating links tables");
 
@@ -32,22 +35,23 @@ pub fn create_links_views(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
-pub fn insert_links(connection: &Connection, links: &[&serde_json::Value], message_key_id: i64) {
-    let mut insert_link_stmt = connection
-        .prepare_cached("INSERT INTO links_raw (link_from_key_id, link_to_key_id) VALUES (?, ?)")
-        .unwrap();
+pub fn insert_links(
+    connection: &Connection,
+    links: &[&serde_json::Value],
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let mut insert_link_stmt = connection.prepare_cached(INSERT_LINK)?;
 
     links
         .iter()
-        .filter(|link| link.is_string())
-        .map(|link| link.as_str().unwrap())
+        .filter_map(|link| link.as_str())
+        .filter_map(normalize_ssb_ref)
         .filter(|link| link.starts_with("%"))
-        .map(|link| find_or_create_key(&connection, link).unwrap())
-        .for_each(|link_id| {
-            insert_link_stmt
-                .execute(&[&message_key_id, &link_id])
-                .unwrap();
-        });
+        .try_for_each(|link| {
+            let link_id = find_or_create_key(&connection, &link)?;
+            insert_link_stmt.execute(&[&message_key_id, &link_id])?;
+            Ok(())
+        })
 }
 
 pub fn create_links_indices(connection: &Connection) -> Result<usize, Error> {
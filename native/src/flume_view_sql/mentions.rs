@@ -1,5 +1,9 @@
 use flume_view_sql::*;
 use rusqlite::{Connection, Error, NO_PARAMS};
+use serde_json::Value;
+
+pub const INSERT_MENTION: &str =
+    "INSERT INTO mentions_raw (link_from_key_id, link_to_author_id) VALUES (?, ?)";
 
 pub fn create_mentions_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating mentions tables");
@@ -14,24 +18,56 @@ pub fn create_mentions_tables(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
-pub fn insert_mentions(connection: &Connection, links: &[&serde_json::Value], message_key_id: i64) {
-    let mut insert_link_stmt = connection
-        .prepare_cached(
-            "INSERT INTO mentions_raw (link_from_key_id, link_to_author_id) VALUES (?, ?)",
-        )
-        .unwrap();
+//Only authors listed in the content's mentions array count, not every @ link in the message.
+pub fn insert_mentions(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    insert_mentions_from_content(connection, &message.value.content, message_key_id)
+}
+
+fn insert_mentions_from_content(
+    connection: &Connection,
+    content: &Value,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    if let Value::Array(mentions) = &content["mentions"] {
+        let mut insert_mention_stmt = connection.prepare_cached(INSERT_MENTION)?;
+
+        mentions
+            .iter()
+            .filter_map(|mention| mention["link"].as_str().or_else(|| mention.as_str()))
+            .filter_map(normalize_ssb_ref)
+            .filter(|link| link.starts_with("@"))
+            .try_for_each(|link| {
+                let author_id = find_or_create_author(&connection, &link)?;
+                insert_mention_stmt.execute(&[&message_key_id, &author_id])?;
+                Ok(())
+            })?;
+    }
+
+    Ok(())
+}
+
+//Older dbs stored a key id in link_to_author_id and took mentions from every @ link, so the
+//table is rebuilt from the content of the stored messages.
+pub fn reindex_mentions(connection: &Connection) -> Result<usize, Error> {
+    trace!("Reindexing mentions");
+    connection.execute("DELETE FROM mentions_raw", NO_PARAMS)?;
+
+    let messages = connection
+        .prepare("SELECT key_id, content FROM messages_raw WHERE content LIKE '%\"mentions\"%'")?
+        .query_map(NO_PARAMS, |row| (row.get(0), row.get(1)))?
+        .collect::<Result<Vec<(i64, String)>, _>>()?;
+
+    for (key_id, content) in &messages {
+        if let Ok(content) = serde_json::from_str::<Value>(content) {
+            insert_mentions_from_content(connection, &content, *key_id)?;
+        }
+    }
 
-    links
-        .iter()
-        .filter(|link| link.is_string())
-        .map(|link| link.as_str().unwrap())
-        .filter(|link| link.starts_with("@"))
-        .map(|link| find_or_create_key(&connection, link).unwrap())
-        .for_each(|link_id| {
-            insert_link_stmt
-                .execute(&[&message_key_id, &link_id])
-                .unwrap();
-        });
+    Ok(messages.len())
 }
 
 pub fn create_mentions_views(connection: &Connection) -> Result<usize, Error> {
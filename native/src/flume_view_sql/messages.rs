@@ -1,40 +1,74 @@
 use rusqlite::types::ToSql;
 use rusqlite::{Connection, Error, NO_PARAMS};
-use serde_json::Value;
 
 use flume_view_sql::*;
 
+pub const INSERT_MESSAGE: &str =
+    "INSERT INTO messages_raw (flume_seq, key_id, seq, received_time, asserted_time, root_id, fork_id, author_id, content_type, content, is_decrypted, raw_content, channel, decryption_result, previous) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+pub const COUNT_OTHER_MESSAGES_AT_SEQUENCE: &str =
+    "SELECT COUNT(*) FROM messages_raw WHERE author_id=?1 AND seq=?2 AND key_id!=?3";
+
+//Resending the very same message isn't a sequence clash, the unique key catches that instead.
+pub fn is_sequence_taken(
+    connection: &Connection,
+    author_id: i64,
+    seq: u32,
+    message_key_id: i64,
+) -> Result<bool, Error> {
+    let count: i64 = connection
+        .prepare_cached(COUNT_OTHER_MESSAGES_AT_SEQUENCE)?
+        .query_row(&[&author_id as &ToSql, &seq, &message_key_id], |row| {
+            row.get(0)
+        })?;
+
+    Ok(count > 0)
+}
+
 pub fn insert_message(
     connection: &Connection,
     message: &SsbMessage,
     seq: i64,
     message_key_id: i64,
-    is_decrypted: bool,
+    decryption_result: DecryptionResult,
+    raw_content: Option<&str>,
 ) -> Result<usize, Error> {
     trace!("prepare stmt");
-    let mut insert_msg_stmt = connection.prepare_cached("INSERT INTO messages_raw (flume_seq, key_id, seq, received_time, asserted_time, root_id, fork_id, author_id, content_type, content, is_decrypted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+    let mut insert_msg_stmt = connection.prepare_cached(INSERT_MESSAGE)?;
 
     trace!("get root key id");
-    let root_key_id = match message.value.content["root"] {
-        Value::String(ref key) => {
-            let id = find_or_create_key(&connection, &key).unwrap();
+    let root_key_id = match message.value.content["root"]
+        .as_str()
+        .and_then(normalize_ssb_ref)
+    {
+        Some(key) => {
+            let id = find_or_create_key(&connection, &key)?;
             Some(id)
         }
-        _ => None,
+        None => None,
     };
 
     trace!("get fork key id");
-    let fork_key_id = match message.value.content["fork"] {
-        Value::String(ref key) => {
-            let id = find_or_create_key(&connection, &key).unwrap();
+    let fork_key_id = match message.value.content["fork"]
+        .as_str()
+        .and_then(normalize_ssb_ref)
+    {
+        Some(key) => {
+            let id = find_or_create_key(&connection, &key)?;
             Some(id)
         }
-        _ => None,
+        None => None,
     };
 
     trace!("find or create author");
     let author_id = find_or_create_author(&connection, &message.value.author)?;
 
+    //Private messages we couldn't decrypt are stored with NULL content.
+    let content = if message.value.content.is_null() {
+        None
+    } else {
+        Some(&message.value.content)
+    };
+
     trace!("insert message");
     insert_msg_stmt.execute(&[
         &seq as &ToSql,
@@ -46,8 +80,12 @@ pub fn insert_message(
         &fork_key_id as &ToSql,
         &author_id,
         &message.value.content["type"].as_str() as &ToSql,
-        &message.value.content as &ToSql,
-        &is_decrypted as &ToSql,
+        &content as &ToSql,
+        &(decryption_result == DecryptionResult::Decrypted) as &ToSql,
+        &raw_content as &ToSql,
+        &message.value.content["channel"].as_str() as &ToSql,
+        &decryption_result.as_str(),
+        &message.value.previous,
     ])
 }
 
@@ -65,12 +103,77 @@ pub fn create_messages_tables(connection: &Connection) -> Result<usize, Error> {
           author_id INTEGER,
           content_type TEXT,
           content JSON,
-          is_decrypted BOOLEAN
+          is_decrypted BOOLEAN,
+          raw_content TEXT,
+          channel TEXT,
+          decryption_result TEXT,
+          previous TEXT
         )",
         NO_PARAMS,
     )
 }
 
+//The ciphertext of private messages is kept so they can be decrypted later, when we learn a new key.
+pub fn add_raw_content_column(connection: &Connection) -> Result<usize, Error> {
+    trace!("Adding raw_content column to messages");
+    if column_exists(connection, "messages_raw", "raw_content")? {
+        return Ok(0);
+    }
+
+    connection.execute(
+        "ALTER TABLE messages_raw ADD COLUMN raw_content TEXT",
+        NO_PARAMS,
+    )
+}
+
+//Backfills the channel of messages that were stored before the column existed.
+pub fn add_channel_column(connection: &Connection) -> Result<usize, Error> {
+    trace!("Adding channel column to messages");
+    if !column_exists(connection, "messages_raw", "channel")? {
+        connection.execute("ALTER TABLE messages_raw ADD COLUMN channel TEXT", NO_PARAMS)?;
+    }
+
+    connection.execute(
+        "UPDATE messages_raw SET channel = json_extract(content, '$.channel')
+        WHERE json_type(content, '$.channel') = 'text'",
+        NO_PARAMS,
+    )?;
+    create_channel_index(connection)
+}
+
+//Only outcomes that can be worked out from what's stored are backfilled. Older failures stay NULL.
+pub fn add_decryption_result_column(connection: &Connection) -> Result<usize, Error> {
+    trace!("Adding decryption_result column to messages");
+    if !column_exists(connection, "messages_raw", "decryption_result")? {
+        connection.execute(
+            "ALTER TABLE messages_raw ADD COLUMN decryption_result TEXT",
+            NO_PARAMS,
+        )?;
+    }
+
+    connection.execute(
+        "UPDATE messages_raw SET decryption_result = 'decrypted' WHERE is_decrypted = 1",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "UPDATE messages_raw SET decryption_result = 'not_encrypted' WHERE raw_content IS NULL",
+        NO_PARAMS,
+    )
+}
+
+//Only the content is stored, so messages from before this column can't be backfilled.
+pub fn add_previous_column(connection: &Connection) -> Result<usize, Error> {
+    trace!("Adding previous column to messages");
+    if column_exists(connection, "messages_raw", "previous")? {
+        return Ok(0);
+    }
+
+    connection.execute(
+        "ALTER TABLE messages_raw ADD COLUMN previous TEXT",
+        NO_PARAMS,
+    )
+}
+
 pub fn create_messages_views(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating messages views");
     connection.execute(
@@ -105,7 +208,11 @@ pub fn create_messages_views(connection: &Connection) -> Result<usize, Error> {
 pub fn create_messages_indices(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating messages indices");
     create_content_type_index(&connection)?;
+    create_decrypted_content_type_index(&connection)?;
     create_root_index(&connection)?;
+    create_received_time_index(&connection)?;
+    create_channel_index(&connection)?;
+    create_seq_indices(&connection)?;
     create_author_index(connection)
 }
 
@@ -117,6 +224,19 @@ fn create_author_index(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
+//author_seq_index covers the common WHERE author_id=? ORDER BY seq pattern.
+pub fn create_seq_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating seq indices");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS seq_index on messages_raw (seq)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS author_seq_index on messages_raw (author_id, seq)",
+        NO_PARAMS,
+    )
+}
+
 fn create_root_index(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating root index");
     connection.execute(
@@ -125,6 +245,22 @@ fn create_root_index(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
+pub fn create_received_time_index(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating received time index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS received_time_index on messages_raw (received_time)",
+        NO_PARAMS,
+    )
+}
+
+fn create_channel_index(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating channel index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS channel_index on messages_raw (channel)",
+        NO_PARAMS,
+    )
+}
+
 fn create_content_type_index(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating content type index");
     connection.execute(
@@ -132,3 +268,12 @@ fn create_content_type_index(connection: &Connection) -> Result<usize, Error> {
         NO_PARAMS,
     )
 }
+
+//Encrypted messages we can't read have no content_type, so they're left out of this one.
+pub fn create_decrypted_content_type_index(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating decrypted content type index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS decrypted_content_type_index on messages_raw (content_type) WHERE content_type IS NOT NULL",
+        NO_PARAMS,
+    )
+}
@@ -1,7 +1,26 @@
+use flume_view_sql::*;
 use rusqlite::{Connection, Error, NO_PARAMS};
 
+//Bumping this deletes the db and rebuilds it from the log. Prefer adding a migration below.
 const MIGRATION_VERSION_NUMBER: u32 = 1;
 
+pub const CURRENT_SCHEMA_VERSION: u32 = 9;
+
+type Migration = fn(&Connection) -> Result<usize, Error>;
+
+//MIGRATIONS[n] moves the schema from version n to n + 1.
+const MIGRATIONS: [Migration; CURRENT_SCHEMA_VERSION as usize] = [
+    create_received_time_index,
+    add_raw_content_column,
+    add_channel_column,
+    create_seq_indices,
+    add_decryption_result_column,
+    create_decrypted_content_type_index,
+    add_previous_column,
+    reindex_mentions,
+    add_contacts_link_from_column,
+];
+
 pub fn create_migrations_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating migrations tables");
 
@@ -14,6 +33,73 @@ pub fn create_migrations_tables(connection: &Connection) -> Result<usize, Error>
     )
 }
 
+pub fn create_schema_version_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating schema_version tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+          id INTEGER PRIMARY KEY,
+          version INTEGER
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn get_schema_version(connection: &Connection) -> Result<u32, Error> {
+    connection
+        .query_row(
+            "SELECT version FROM schema_version WHERE id = 0",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+        .or_else(|err| match err {
+            Error::QueryReturnedNoRows => Ok(0),
+            err => Err(err),
+        })
+}
+
+//The version is mirrored to PRAGMA user_version so tools that only know sqlite can see it.
+pub fn set_schema_version(connection: &Connection, version: u32) -> Result<usize, Error> {
+    set_user_version(connection, version as i32)?;
+    connection.execute(
+        "REPLACE INTO schema_version (id, version) VALUES (0, ?)",
+        &[&version],
+    )
+}
+
+pub fn get_user_version(connection: &Connection) -> Result<i32, Error> {
+    connection.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+}
+
+pub fn set_user_version(connection: &Connection, version: i32) -> Result<usize, Error> {
+    connection.execute(&format!("PRAGMA user_version = {}", version), NO_PARAMS)
+}
+
+//Lets migrations that add a column be re-run safely.
+pub fn column_exists(connection: &Connection, table: &str, column: &str) -> Result<bool, Error> {
+    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map(NO_PARAMS, |row| row.get(1))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    Ok(columns.iter().any(|name| name == column))
+}
+
+pub fn run_migrations(connection: &Connection) -> Result<(), Error> {
+    create_schema_version_tables(connection)?;
+    let version = get_schema_version(connection)?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let next_version = index as u32 + 1;
+        info!("Migrating sqlite db to schema version {}", next_version);
+
+        migration(connection)?;
+        set_schema_version(connection, next_version)?;
+    }
+
+    Ok(())
+}
+
 pub fn is_db_up_to_date(connection: &Connection) -> Result<bool, Error> {
     connection
         .query_row_and_then("SELECT version FROM migrations LIMIT 1", NO_PARAMS, |row| {
@@ -1,110 +1,468 @@
 use failure::Error;
 use flumedb::flume_view::*;
+use std::collections::HashMap;
 
 use base64::decode;
+use bloom::{BloomFilter, ASMS};
+use rusqlite::backup::Progress;
+use rusqlite::ffi;
 use rusqlite::types::ToSql;
 use rusqlite::OpenFlags;
-use rusqlite::{Connection, NO_PARAMS};
-use serde_json::Value;
+use rusqlite::{Connection, DatabaseName, Row, NO_PARAMS};
+use serde_json::{json, Value};
 
 use private_box::SecretKey;
 
+use ssb_types::*;
+
 mod abouts;
+#[cfg(feature = "async")]
+mod async_append;
 mod authors;
 mod blob_links;
 mod blobs;
+#[cfg(feature = "box2")]
+mod box2;
 mod branches;
+mod builder;
 mod contacts;
+mod ext;
+#[cfg(feature = "fts")]
+mod fts;
+mod heads;
+mod iter;
 mod keys;
 mod links;
 mod mentions;
 mod messages;
 mod migrations;
+#[cfg(test)]
+pub mod test_fixtures;
 use self::abouts::*;
 use self::authors::*;
 use self::blob_links::*;
 use self::blobs::*;
+#[cfg(feature = "box2")]
+use self::box2::*;
 use self::branches::*;
+pub use self::builder::{AutoVacuumMode, FlumeViewSqlBuilder, SynchronousMode, TempStoreMode};
 use self::contacts::*;
+pub use self::ext::FlumeViewSqlExt;
+#[cfg(feature = "fts")]
+use self::fts::*;
+use self::heads::*;
+use self::iter::*;
 use self::keys::*;
 use self::links::*;
 use self::mentions::*;
 use self::messages::*;
 use self::migrations::*;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SsbValue {
-    author: String,
-    sequence: u32,
-    timestamp: f64,
-    content: Value,
-}
+//The root message comes first, followed by the replies. Replies are found through root_id so
+//the lookup can use the root_id_index instead of scanning every message.
+const THREAD_BY_ROOT_QUERY: &str = "
+    SELECT key FROM (
+        SELECT keys.key AS key, seq, 0 AS is_reply
+        FROM messages_raw JOIN keys ON keys.id=messages_raw.key_id
+        WHERE keys.key=?1
+        UNION
+        SELECT keys.key AS key, seq, 1 AS is_reply
+        FROM messages_raw JOIN keys ON keys.id=messages_raw.key_id
+        WHERE messages_raw.root_id=(SELECT id FROM keys WHERE key=?1)
+    ) ORDER BY is_reply, seq ASC
+    ";
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SsbMessage {
-    key: String,
-    value: SsbValue,
-    timestamp: f64,
-}
+//Walks up root and branch pointers from ?1, at most ?2 steps. Ancestors we only know the key of
+//are included too. The depth limit stops malformed data with a cycle from looping forever.
+const CAUSAL_ANCESTORS_QUERY: &str = "
+    WITH RECURSIVE ancestors(key_id, depth) AS (
+        SELECT id, 0 FROM keys WHERE key=?1
+        UNION
+        SELECT parents.parent_id, ancestors.depth + 1
+        FROM ancestors JOIN (
+            SELECT key_id AS child_id, root_id AS parent_id FROM messages_raw
+            WHERE root_id IS NOT NULL
+            UNION ALL
+            SELECT link_from_key_id, link_to_key_id FROM branches_raw
+        ) AS parents ON parents.child_id=ancestors.key_id
+        WHERE ancestors.depth < ?2
+    )
+    SELECT keys.key FROM ancestors JOIN keys ON keys.id=ancestors.key_id
+    WHERE depth > 0
+    GROUP BY ancestors.key_id
+    ORDER BY MIN(depth), keys.key
+    ";
+
+//Every stored column, with ids swapped for the keys and authors they point at.
+const EXPORT_QUERY: &str = "
+    SELECT flume_seq, keys.key, authors.author, seq, received_time, asserted_time,
+        root_keys.key, fork_keys.key, content_type, content, is_decrypted, raw_content,
+        channel, decryption_result, previous
+    FROM messages_raw
+    JOIN keys ON keys.id=messages_raw.key_id
+    JOIN authors ON authors.id=messages_raw.author_id
+    LEFT JOIN keys AS root_keys ON root_keys.id=messages_raw.root_id
+    LEFT JOIN keys AS fork_keys ON fork_keys.id=messages_raw.fork_id
+    ORDER BY flume_seq
+    ";
+
+//Columns read by ssb_message_from_row, in order. Callers append their own WHERE clause.
+const SELECT_SSB_MESSAGES: &str = "
+    SELECT keys.key, authors.author, seq, asserted_time, received_time, content, raw_content,
+    previous
+    FROM messages_raw
+    JOIN keys ON keys.id=messages_raw.key_id
+    JOIN authors ON authors.id=messages_raw.author_id
+    ";
+
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+//The key filter is sized for twice the stored messages so it stays accurate while the db grows.
+const KEY_FILTER_FALSE_POSITIVE_RATE: f32 = 0.01;
+const KEY_FILTER_MIN_CAPACITY: u32 = 100_000;
+
+//sqlite's default SQLITE_MAX_VARIABLE_NUMBER.
+const MAX_SQL_PARAMS: usize = 999;
+
+const LATEST_FLUME_SEQ_QUERY: &str = "SELECT MAX(flume_seq) FROM messages_raw";
+
+const MESSAGE_JSON_QUERY: &str =
+    "SELECT content FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key=?1";
+
+//Every statement append_item prepares, plus the hottest reads.
+const WARM_STATEMENTS: [&str; 18] = [
+    SELECT_KEY_ID,
+    INSERT_KEY,
+    SELECT_AUTHOR_ID,
+    INSERT_AUTHOR,
+    SELECT_BLOB_ID,
+    INSERT_BLOB,
+    COUNT_OTHER_MESSAGES_AT_SEQUENCE,
+    INSERT_MESSAGE,
+    INSERT_LINK,
+    INSERT_BLOB_LINK,
+    INSERT_MENTION,
+    INSERT_BRANCH,
+    DELETE_BUILT_ON_HEADS,
+    INSERT_HEAD,
+    REPLACE_CONTACT,
+    INSERT_ABOUT,
+    LATEST_FLUME_SEQ_QUERY,
+    MESSAGE_JSON_QUERY,
+];
 
 #[derive(Debug, Fail)]
 pub enum FlumeViewSqlError {
-    #[fail(display = "Db failed integrity check")]
-    DbFailedIntegrityCheck {},
+    #[fail(display = "Db failed integrity check: {:?}", details)]
+    DbFailedIntegrityCheck { details: Vec<String> },
+    #[fail(
+        display = "Requested journal mode {} but sqlite is using {}",
+        requested, actual
+    )]
+    JournalModeNotSet { requested: String, actual: String },
+    #[fail(display = "{} isn't an sqlite journal mode", mode)]
+    InvalidJournalMode { mode: String },
+    #[fail(display = "Cache size has to be at least 1 KiB")]
+    InvalidCacheSize {},
+    #[fail(display = "Can't write to a view opened read only")]
+    ReadOnly {},
+    #[fail(display = "The blocking append task panicked or was cancelled")]
+    AsyncSpawnFailed {},
+    #[fail(display = "The view's mutex was poisoned by a panic while it was held")]
+    ViewLockPoisoned {},
+    #[fail(
+        display = "Feed of {} expected sequence {} but found {}",
+        author, expected, found
+    )]
+    FeedGap {
+        author: String,
+        expected: u32,
+        found: u32,
+    },
+    #[fail(display = "Message at sequence {} doesn't point back to the one before it", at_seq)]
+    BrokenHashChain { at_seq: u32 },
+    #[fail(display = "Private message uses unsupported encryption .{}", suffix)]
+    UnsupportedEncryption { suffix: String },
+    #[fail(
+        display = "Db schema version {} isn't one this build can open (expected {})",
+        found, expected
+    )]
+    UnrecognizedSchema { found: u32, expected: u32 },
+    #[fail(display = "Feed of {} already has a message at sequence {}", author, seq)]
+    DuplicateSequence { author: String, seq: u32 },
+    #[fail(display = "Message is {} bytes, more than the limit of {}", size, limit)]
+    MessageTooLarge { size: usize, limit: usize },
+    #[fail(display = "Messages of type {} aren't allowed", content_type)]
+    DisallowedContentType { content_type: String },
+    #[fail(display = "{} can't be used as a database alias", alias)]
+    InvalidDatabaseAlias { alias: String },
+    #[fail(display = "{} is not a valid sqlite file: URI", uri)]
+    InvalidUri { uri: String },
+    #[fail(display = "Already have a message with key {}", key)]
+    DuplicateKey { key: String },
+    #[fail(display = "Time range starts at {} which is after its end {}", start, end)]
+    InvalidTimeRange { start: f64, end: f64 },
+}
+
+/// What happened when we tried to read a message's content. Failures are kept so operators can
+/// tell a message for someone else from a corrupt one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecryptionResult {
+    NotEncrypted,
+    Decrypted,
+    FailedNoMatchingKey,
+    FailedInvalidCiphertext,
+    FailedInvalidJson,
+}
+
+impl DecryptionResult {
+    /// How the result is stored in the decryption_result column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DecryptionResult::NotEncrypted => "not_encrypted",
+            DecryptionResult::Decrypted => "decrypted",
+            DecryptionResult::FailedNoMatchingKey => "failed_no_matching_key",
+            DecryptionResult::FailedInvalidCiphertext => "failed_invalid_ciphertext",
+            DecryptionResult::FailedInvalidJson => "failed_invalid_json",
+        }
+    }
+}
+
+/// What happened to one item of an `append_batch`. A skipped item carries why it failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppendResult {
+    Ok(Sequence),
+    Skipped(Sequence, String),
+}
+
+/// How hard `wal_checkpoint` tries. See sqlite's docs for `PRAGMA wal_checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WalCheckpointMode {
+    Passive,
+    Full,
+    Restart,
+    Truncate,
+}
+
+impl WalCheckpointMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WalCheckpointMode::Passive => "PASSIVE",
+            WalCheckpointMode::Full => "FULL",
+            WalCheckpointMode::Restart => "RESTART",
+            WalCheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// How far `rebuild_indices_with_progress` has got. `step` of `total` tables have their indices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexProgress {
+    pub index_name: &'static str,
+    pub step: u8,
+    pub total: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbStats {
+    pub message_count: i64,
+    pub author_count: i64,
+    pub link_count: i64,
+    pub db_size_bytes: u64,
 }
 
 pub struct FlumeViewSql {
     connection: Connection,
     secret_keys: Vec<SecretKey>,
+    read_only: bool,
+    batch_chunk_size: Option<usize>,
+    max_message_size: Option<usize>,
+    content_type_allowlist: Option<Vec<String>>,
+    bloom: Option<BloomFilter>,
 }
 
 impl FlumeView for FlumeViewSql {
     fn append(&mut self, seq: Sequence, item: &[u8]) {
-        append_item(&self.connection, &self.secret_keys, seq, item).unwrap()
+        if let Err(err) = self.append_with_result(seq, item) {
+            warn!("Skipping item at seq {}, failed to append: {}", seq, err);
+        }
     }
     fn latest(&self) -> Sequence {
         self.get_latest().unwrap()
     }
 }
 
-fn create_connection(path: &str) -> Result<Connection, Error> {
+fn create_connection(path: &str, extra_flags: OpenFlags) -> Result<Connection, Error> {
     let flags: OpenFlags = OpenFlags::SQLITE_OPEN_READ_WRITE
         | OpenFlags::SQLITE_OPEN_CREATE
-        | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        | OpenFlags::SQLITE_OPEN_NO_MUTEX
+        | extra_flags;
 
     Connection::open_with_flags(path, flags).map_err(|err| err.into())
 }
 
+//Views first, since they depend on the tables. Dropping a virtual table takes its shadow tables
+//with it, hence IF EXISTS.
+fn drop_schema(connection: &Connection) -> Result<(), Error> {
+    let schema = connection
+        .prepare(
+            "SELECT type, name FROM sqlite_master WHERE type IN ('view', 'table') AND name NOT LIKE 'sqlite_%' ORDER BY type='table', rowid",
+        )?
+        .query_map(NO_PARAMS, |row| (row.get(0), row.get(1)))?
+        .collect::<Result<Vec<(String, String)>, _>>()?;
+
+    for (kind, name) in schema {
+        trace!("Dropping {} {}", kind, name);
+        connection.execute(&format!("DROP {} IF EXISTS {}", kind, name), NO_PARAMS)?;
+    }
+
+    Ok(())
+}
+
+//The query parameters of a `file:` URI as (name, value) pairs.
+fn parse_uri_params(uri: &str) -> Result<Vec<(&str, &str)>, Error> {
+    let invalid = || FlumeViewSqlError::InvalidUri {
+        uri: uri.to_string(),
+    };
+
+    if !uri.starts_with("file:") {
+        return Err(invalid().into());
+    }
+
+    match uri.find('?') {
+        None => Ok(Vec::new()),
+        Some(start) => uri[start + 1..]
+            .split('&')
+            .map(|param| {
+                let mut parts = param.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if !name.is_empty() => Ok((name, value)),
+                    _ => Err(invalid().into()),
+                }
+            })
+            .collect(),
+    }
+}
+
+fn is_in_memory_uri(uri: &str) -> bool {
+    uri.starts_with("file::memory:")
+        || parse_uri_params(uri)
+            .map(|params| params.contains(&("mode", "memory")))
+            .unwrap_or(false)
+}
+
 impl FlumeViewSql {
     pub fn new(
         path: &str,
         secret_keys: Vec<SecretKey>,
         pub_key: &str,
     ) -> Result<FlumeViewSql, Error> {
-        let mut connection = create_connection(path)?;
-
-        if let Ok(false) = is_db_up_to_date(&connection) {
-            info!("sqlite db is out of date. Deleting db and it will be rebuilt.");
-            std::fs::remove_file(path).unwrap();
+        FlumeViewSqlBuilder::new()
+            .path(path)
+            .keys(secret_keys)
+            .pub_key(pub_key)
+            .build()
+    }
 
-            connection = create_connection(path)?;
+    /// Opens a db created by `new`, erroring with `FlumeViewSqlError::UnrecognizedSchema` rather
+    /// than rebuilding it when its schema doesn't match this build.
+    pub fn open_existing(path: &str, secret_keys: Vec<SecretKey>) -> Result<FlumeViewSql, Error> {
+        FlumeViewSqlBuilder::new()
+            .path(path)
+            .keys(secret_keys)
+            .existing_only()
+            .build()
+    }
 
-            create_tables(&connection)?;
-            create_indices(&connection)?;
-            create_views(&connection)?;
+    /// Opens an existing db without creating tables or changing any pragmas. Every write fails
+    /// with `FlumeViewSqlError::ReadOnly`.
+    pub fn open_read_only(path: &str, secret_keys: Vec<SecretKey>) -> Result<FlumeViewSql, Error> {
+        FlumeViewSql::open_read_only_with_flags(path, OpenFlags::empty(), secret_keys)
+    }
 
-            set_db_version(&connection)?;
-            set_author_that_is_me(&connection, pub_key)?;
+    /// Opens a db from an sqlite `file:` URI, eg. `file:name?mode=memory&cache=shared` for an
+    /// in-memory db that every connection with the same name shares. With `mode=ro` the view is
+    /// read only, like `open_read_only`. Errors with `FlumeViewSqlError::InvalidUri` if `uri`
+    /// doesn't parse.
+    pub fn from_uri(uri: &str, secret_keys: Vec<SecretKey>) -> Result<FlumeViewSql, Error> {
+        if parse_uri_params(uri)?.contains(&("mode", "ro")) {
+            return FlumeViewSql::open_read_only_with_flags(
+                uri,
+                OpenFlags::SQLITE_OPEN_URI,
+                secret_keys,
+            );
         }
 
-        set_pragmas(&connection);
+        FlumeViewSqlBuilder::new()
+            .uri(uri)
+            .keys(secret_keys)
+            .build()
+    }
+
+    fn open_read_only_with_flags(
+        path: &str,
+        extra_flags: OpenFlags,
+        secret_keys: Vec<SecretKey>,
+    ) -> Result<FlumeViewSql, Error> {
+        let flags: OpenFlags =
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | extra_flags;
+        let connection = Connection::open_with_flags(path, flags)?;
 
         Ok(FlumeViewSql {
             connection,
             secret_keys,
+            read_only: true,
+            batch_chunk_size: None,
+            max_message_size: Some(DEFAULT_MAX_MESSAGE_SIZE),
+            content_type_allowlist: None,
+            bloom: None,
         })
     }
 
+    /// False if no message with `key` is stored, true if one probably is. Much cheaper than
+    /// `get_seq_by_key`, so it can weed out new keys first. Always true unless the view was built
+    /// with `FlumeViewSqlBuilder::key_filter`, and for read only views.
+    ///
+    /// The filter is loaded when the view is opened and only learns about this view's own
+    /// appends. If another connection writes to the same db, a false answer can be wrong until
+    /// this view is reopened or `reload_key_filter` is called.
+    pub fn key_probably_exists(&self, key: &str) -> bool {
+        self.bloom
+            .as_ref()
+            .map_or(true, |bloom| bloom.contains(&key))
+    }
+
+    /// Rebuilds the key filter from the db, eg. after another connection has appended to it.
+    /// Does nothing if the view has no key filter.
+    pub fn reload_key_filter(&mut self) -> Result<(), Error> {
+        if self.bloom.is_some() {
+            self.bloom = Some(load_key_filter(&self.connection)?);
+        }
+        Ok(())
+    }
+
+    fn remember_keys(&mut self, keys: &[String]) {
+        if let Some(bloom) = self.bloom.as_mut() {
+            for key in keys {
+                bloom.insert(key);
+            }
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(FlumeViewSqlError::ReadOnly {}.into());
+        }
+        Ok(())
+    }
+
+    pub fn new_in_memory(secret_keys: Vec<SecretKey>) -> Result<FlumeViewSql, Error> {
+        FlumeViewSqlBuilder::new()
+            .in_memory()
+            .keys(secret_keys)
+            .build()
+    }
+
     pub fn get_seq_by_key(&mut self, key: &str) -> Result<i64, Error> {
         let mut stmt = self
             .connection
@@ -114,6 +472,151 @@ impl FlumeViewSql {
             .map_err(|err| err.into())
     }
 
+    /// Flume sequences for many keys in one query. Keys we don't have are left out of the map.
+    pub fn get_seqs_by_keys(&mut self, keys: &[&str]) -> Result<HashMap<String, i64>, Error> {
+        let mut seqs = HashMap::<String, i64>::new();
+
+        //sqlite limits how many parameters one statement can bind.
+        for chunk in keys.chunks(MAX_SQL_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = self.connection.prepare(&format!(
+                "SELECT keys.key, flume_seq FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key IN ({})",
+                placeholders
+            ))?;
+
+            let rows = stmt.query_map(chunk, |row| (row.get(0), row.get(1)))?;
+
+            for row in rows {
+                let (key, seq) = row?;
+                seqs.insert(key, seq);
+            }
+        }
+
+        Ok(seqs)
+    }
+
+    pub fn get_message_by_key(&mut self, key: &str) -> Result<Option<SsbMessage>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached(&format!("{} WHERE keys.key=?1", SELECT_SSB_MESSAGES))?;
+
+        let mut rows = stmt.query_and_then(&[key], ssb_message_from_row)?;
+
+        rows.next().transpose()
+    }
+
+    pub fn get_message_json(&mut self, key: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(MESSAGE_JSON_QUERY)?;
+
+        //Content is NULL for private messages that couldn't be decrypted.
+        match stmt.query_row(&[key], |row| row.get(0)) {
+            Ok(content) => Ok(content),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The key of the message before `key` in its author's feed. None for the first message of a
+    /// feed, and for messages stored before the previous column was added.
+    pub fn get_previous_key(&mut self, key: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT previous FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key=?1",
+        )?;
+
+        match stmt.query_row(&[key], |row| row.get(0)) {
+            Ok(previous) => Ok(previous),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The boxed ciphertext a private message was received as. None for public messages.
+    pub fn get_raw_content(&mut self, key: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT raw_content FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key=?1",
+        )?;
+
+        match stmt.query_row(&[key], |row| row.get(0)) {
+            Ok(raw_content) => Ok(raw_content),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn get_decrypted_messages(&mut self, limit: i64, offset: i64) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE is_decrypted=1 ORDER BY flume_seq LIMIT ?1 OFFSET ?2",
+        )?;
+
+        let rows = stmt.query_map(&[&limit, &offset], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Keys of private messages that none of our secret keys could decrypt.
+    pub fn get_encrypted_failed_messages(
+        &mut self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE content IS NULL AND is_decrypted=0 ORDER BY flume_seq LIMIT ?1 OFFSET ?2",
+        )?;
+
+        let rows = stmt.query_map(&[&limit, &offset], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Keys of all private messages, newest first, with whether we could decrypt each one.
+    /// Decrypted messages take their content_type from the decrypted content, so private ones are
+    /// found by the ciphertext kept in raw_content instead.
+    pub fn get_private_message_keys(
+        &mut self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<(String, bool)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key, is_decrypted FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE raw_content IS NOT NULL ORDER BY flume_seq DESC LIMIT ?1 OFFSET ?2",
+        )?;
+
+        let rows = stmt.query_map(&[&limit, &offset], |row| (row.get(0), row.get(1)))?;
+
+        let keys = rows.fold(Vec::<(String, bool)>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// (key, reason) for every private message we couldn't read. Messages stored before reasons
+    /// were recorded have the reason "unknown".
+    pub fn get_decryption_failures(&mut self) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key, COALESCE(decryption_result, 'unknown') FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE content IS NULL AND raw_content IS NOT NULL ORDER BY flume_seq",
+        )?;
+
+        let rows = stmt.query_map(NO_PARAMS, |row| (row.get(0), row.get(1)))?;
+
+        let failures = rows.fold(Vec::<(String, String)>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(failures)
+    }
+
     pub fn get_seqs_by_type(&mut self, content_type: &str) -> Result<Vec<i64>, Error> {
         let mut stmt = self
             .connection
@@ -129,12 +632,39 @@ impl FlumeViewSql {
         Ok(seqs)
     }
 
-    pub fn get_seqs_by_author(&mut self, author: &str) -> Result<Vec<i64>, Error> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT flume_seq FROM messages_raw JOIN authors ON messages_raw.author_id=authors.id WHERE author=?1")?;
+    /// Runs `sql`, which must select a message key then its `flume_seq`, and yields the keys in
+    /// flume seq order without collecting every row.
+    pub fn query_messages_iter<'a>(
+        &'a mut self,
+        sql: &str,
+        params: &[&ToSql],
+    ) -> Result<impl Iterator<Item = Result<String, Error>> + 'a, Error> {
+        MessagesIter::new(&self.connection, sql, params)
+    }
 
-        let rows = stmt.query_map(&[author], |row| row.get(0))?;
+    pub fn iter_messages_by_type<'a>(
+        &'a mut self,
+        content_type: &str,
+    ) -> Result<impl Iterator<Item = Result<String, Error>> + 'a, Error> {
+        self.query_messages_iter(
+            "SELECT keys.key, flume_seq FROM messages_raw JOIN keys ON keys.id=messages_raw.key_id WHERE content_type=?1",
+            &[&content_type as &ToSql],
+        )
+    }
+
+    pub fn get_seqs_by_type_paged(
+        &mut self,
+        content_type: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<i64>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT flume_seq FROM messages_raw WHERE content_type=?1 ORDER BY flume_seq LIMIT ?2 OFFSET ?3",
+        )?;
+
+        let rows = stmt.query_map(&[&content_type as &ToSql, &limit, &offset], |row| {
+            row.get(0)
+        })?;
 
         let seqs = rows.fold(Vec::<i64>::new(), |mut vec, row| {
             vec.push(row.unwrap());
@@ -144,280 +674,4627 @@ impl FlumeViewSql {
         Ok(seqs)
     }
 
-    pub fn append_batch(&mut self, items: &[(Sequence, Vec<u8>)]) {
-        trace!("Start batch append");
-        let tx = self.connection.transaction().unwrap();
+    pub fn get_all_content_types(&mut self) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT DISTINCT content_type FROM messages_raw WHERE content_type IS NOT NULL ORDER BY content_type",
+        )?;
 
-        for item in items {
-            append_item(&tx, &self.secret_keys, item.0, &item.1).unwrap();
-        }
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
 
-        tx.commit().unwrap();
-    }
+        let content_types = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
 
-    pub fn check_db_integrity(&mut self) -> Result<(), Error> {
-        self.connection
-            .query_row_and_then("PRAGMA integrity_check", NO_PARAMS, |row| {
-                row.get_checked(0)
-                    .map_err(|err| err.into())
-                    .and_then(|res: String| {
-                        if res == "ok" {
-                            return Ok(());
-                        }
-                        return Err(FlumeViewSqlError::DbFailedIntegrityCheck {}.into());
-                    })
-            })
+        Ok(content_types)
     }
 
-    pub fn get_latest(&self) -> Result<Sequence, Error> {
+    pub fn get_count_by_type(&mut self, content_type: &str) -> Result<i64, Error> {
         let mut stmt = self
             .connection
-            .prepare_cached("SELECT MAX(flume_seq) FROM messages_raw")?;
+            .prepare_cached("SELECT COUNT(*) FROM messages_raw WHERE content_type=?1")?;
 
-        stmt.query_row(NO_PARAMS, |row| {
-            let res: i64 = row.get_checked(0).unwrap_or(0);
-            trace!("got latest seq from db: {}", res);
-            res as Sequence
-        })
-        .map_err(|err| err.into())
+        stmt.query_row(&[content_type], |row| row.get(0))
+            .map_err(|err| err.into())
     }
-}
 
-fn find_values_in_object_by_key<'a>(
-    obj: &'a serde_json::Value,
-    key: &str,
-    values: &mut Vec<&'a serde_json::Value>,
-) {
-    match obj.get(key) {
-        Some(val) => values.push(val),
-        _ => (),
-    };
+    pub fn get_total_message_count(&mut self) -> Result<i64, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT COUNT(*) FROM messages_raw")?;
 
-    match obj {
-        Value::Array(arr) => {
-            for val in arr {
-                find_values_in_object_by_key(val, key, values);
-            }
-        }
-        Value::Object(kv) => {
-            for val in kv.values() {
-                match val {
-                    Value::Object(_) => find_values_in_object_by_key(val, key, values),
-                    Value::Array(_) => find_values_in_object_by_key(val, key, values),
-                    _ => (),
-                }
-            }
-        }
-        _ => (),
+        stmt.query_row(NO_PARAMS, |row| row.get(0))
+            .map_err(|err| err.into())
     }
-}
 
-fn attempt_decryption(mut message: SsbMessage, secret_keys: &[SecretKey]) -> (bool, SsbMessage) {
-    let mut is_decrypted = false;
+    pub fn get_messages_by_channel(&mut self, channel: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE channel=?1 ORDER BY flume_seq",
+        )?;
 
-    message = match message.value.content["type"] {
-        Value::Null => {
-            let content = message.value.content.clone();
-            let strrr = &content.as_str().unwrap().trim_end_matches(".box");
+        let rows = stmt.query_map(&[channel], |row| row.get(0))?;
 
-            let bytes = decode(strrr).unwrap();
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
 
-            for secret_key in secret_keys {
-                message.value.content = private_box::decrypt(&bytes, secret_key)
-                    .and_then(|data| {
-                        is_decrypted = true;
-                        serde_json::from_slice(&data).map_err(|_| ())
-                    })
-                    .unwrap_or(Value::Null); //If we can't decrypt it, throw it away.
+        Ok(keys)
+    }
 
-                if is_decrypted {
-                    break;
-                }
-            }
+    pub fn get_all_channels(&mut self) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT DISTINCT channel FROM messages_raw WHERE channel IS NOT NULL ORDER BY channel",
+        )?;
 
-            message
-        }
-        _ => message,
-    };
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
 
-    (is_decrypted, message)
-}
+        let channels = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
 
-fn append_item(
-    connection: &Connection,
-    secret_keys: &[SecretKey],
-    seq: Sequence,
-    item: &[u8],
-) -> Result<(), Error> {
-    let message: SsbMessage = serde_json::from_slice(item).unwrap();
+        Ok(channels)
+    }
 
-    let (is_decrypted, message) = attempt_decryption(message, secret_keys);
+    pub fn get_seqs_by_author(&mut self, author: &str) -> Result<Vec<i64>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT flume_seq FROM messages_raw JOIN authors ON messages_raw.author_id=authors.id WHERE author=?1")?;
 
-    let message_key_id = find_or_create_key(&connection, &message.key).unwrap();
+        let rows = stmt.query_map(&[author], |row| row.get(0))?;
 
-    let mut links = Vec::new();
-    find_values_in_object_by_key(&message.value.content, "link", &mut links);
+        let seqs = rows.fold(Vec::<i64>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
 
-    insert_links(connection, links.as_slice(), message_key_id);
-    insert_mentions(connection, links.as_slice(), message_key_id);
-    insert_blob_links(connection, links.as_slice(), message_key_id);
+        Ok(seqs)
+    }
 
-    insert_branches(connection, &message, message_key_id);
-    insert_message(
-        connection,
-        &message,
-        seq as i64,
-        message_key_id,
-        is_decrypted,
-    )?;
-    insert_or_update_contacts(connection, &message, message_key_id, is_decrypted);
-    insert_abouts(connection, &message, message_key_id);
+    pub fn get_feed_for_author(&mut self, author: &str) -> Result<Vec<SsbMessage>, Error> {
+        let mut stmt = self.connection.prepare_cached(&format!(
+            "{} WHERE authors.author=?1 ORDER BY seq",
+            SELECT_SSB_MESSAGES
+        ))?;
 
-    Ok(())
-}
+        let rows = stmt.query_and_then(&[author], ssb_message_from_row)?;
 
-fn set_pragmas(connection: &Connection) {
-    connection
-        .execute("PRAGMA synchronous = OFF", NO_PARAMS)
-        .unwrap();
-    connection
-        .execute("PRAGMA page_size = 4096", NO_PARAMS)
-        .unwrap();
-}
+        rows.collect()
+    }
 
-fn create_tables(connection: &Connection) -> Result<(), Error> {
-    create_migrations_tables(connection)?;
-    create_messages_tables(connection)?;
-    create_authors_tables(connection)?;
-    create_keys_tables(connection)?;
-    create_links_tables(connection)?;
-    create_contacts_tables(connection)?;
-    create_branches_tables(connection)?;
-    create_mentions_tables(connection)?;
-    create_abouts_tables(connection)?;
-    create_blobs_tables(connection)?;
-    create_blob_links_tables(connection)?;
+    pub fn get_seqs_by_type_and_author(
+        &mut self,
+        content_type: &str,
+        author: &str,
+    ) -> Result<Vec<i64>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT flume_seq FROM messages_raw JOIN authors ON messages_raw.author_id=authors.id WHERE content_type=?1 AND author=?2 ORDER BY seq",
+        )?;
 
-    Ok(())
-}
+        let rows = stmt.query_map(&[content_type, author], |row| row.get(0))?;
 
-fn create_views(connection: &Connection) -> Result<(), Error> {
-    create_messages_views(connection)?;
-    create_links_views(connection)?;
-    create_blob_links_views(connection)?;
-    create_abouts_views(connection)?;
-    create_mentions_views(connection)?;
-    Ok(())
-}
+        let seqs = rows.fold(Vec::<i64>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(seqs)
+    }
+
+    /// Keys of `author`'s messages in sequence order. `database` is the alias of a db added with
+    /// `attach_database` to look in, or None for this one.
+    pub fn get_messages_by_author(
+        &mut self,
+        author: &str,
+        database: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        let database = database.unwrap_or("main");
+        check_database_alias(database)?;
+
+        let mut stmt = self.connection.prepare_cached(&format!(
+            "SELECT key FROM {0}.messages WHERE author_id = (SELECT id FROM {0}.authors WHERE author=?1) ORDER BY seq ASC",
+            database
+        ))?;
+
+        let rows = stmt.query_map(&[author], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Every author that has published at least one message, sorted by key.
+    pub fn get_authors_list(&mut self) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT author FROM authors WHERE id IN (SELECT author_id FROM messages_raw) ORDER BY author",
+        )?;
+
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+
+        let authors = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(authors)
+    }
+
+    /// The latest (contact, following, blocking) state for every contact `author` has published
+    /// about, sorted by contact.
+    pub fn get_contacts_for_author(
+        &mut self,
+        author: &str,
+    ) -> Result<Vec<(String, bool, bool)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT content FROM messages_raw WHERE content_type='contact' AND author_id=(SELECT id FROM authors WHERE author=?1) ORDER BY seq",
+        )?;
+
+        let contents = stmt
+            .query_map(&[author], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        //Messages are in seq order so a later message about the same contact overwrites an earlier one.
+        let mut latest = HashMap::<String, (bool, bool)>::new();
+        for content in contents {
+            let content: Value = serde_json::from_str(&content)?;
+
+            if let Some(contact) = contact_of(&content) {
+                let following = content["following"].as_bool().unwrap_or(false);
+                let blocking = content["blocking"].as_bool().unwrap_or(false);
+                latest.insert(contact, (following, blocking));
+            }
+        }
+
+        let mut contacts = latest
+            .into_iter()
+            .map(|(contact, (following, blocking))| (contact, following, blocking))
+            .collect::<Vec<_>>();
+        contacts.sort();
+
+        Ok(contacts)
+    }
+
+    /// Authors `author` currently follows and isn't blocking, sorted.
+    pub fn get_follows_for_author(&mut self, author: &str) -> Result<Vec<String>, Error> {
+        let follows = self
+            .get_contacts_for_author(author)?
+            .into_iter()
+            .filter(|(_, following, blocking)| *following && !*blocking)
+            .map(|(contact, _, _)| contact)
+            .collect();
+
+        Ok(follows)
+    }
+
+    /// Authors whose latest contact message about `author` follows them without blocking, sorted.
+    pub fn get_followers_for_author(&mut self, author: &str) -> Result<Vec<String>, Error> {
+        //The LIKE only narrows down the rows to parse, the contact field is checked below.
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT authors.author, content FROM messages_raw JOIN authors ON authors.id=messages_raw.author_id WHERE content_type='contact' AND (content LIKE '%' || ?1 || '%' OR content LIKE '%' || ?2 || '%') ORDER BY messages_raw.author_id, seq",
+        )?;
+
+        let uri_term = uri_search_term(author);
+        let rows = stmt
+            .query_map(&[author, uri_term.as_str()], |row| (row.get(0), row.get(1)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+
+        //Messages are in seq order so a later message from the same author overwrites an earlier one.
+        let mut latest = HashMap::<String, bool>::new();
+        for (follower, content) in rows {
+            let content: Value = serde_json::from_str(&content)?;
+
+            if contact_of(&content).as_ref().map(String::as_str) == Some(author) {
+                let following = content["following"].as_bool().unwrap_or(false);
+                let blocking = content["blocking"].as_bool().unwrap_or(false);
+                latest.insert(follower, following && !blocking);
+            }
+        }
+
+        let mut followers = latest
+            .into_iter()
+            .filter(|(_, following)| *following)
+            .map(|(follower, _)| follower)
+            .collect::<Vec<_>>();
+        followers.sort();
+
+        Ok(followers)
+    }
+
+    /// Every author's latest contact edges as (contact, is_following) pairs sorted by contact.
+    /// Blocking and unfollowing both leave is_following false.
+    pub fn get_social_graph(&mut self) -> Result<HashMap<String, Vec<(String, bool)>>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT authors.author, content FROM messages_raw JOIN authors ON authors.id=messages_raw.author_id WHERE content_type='contact' ORDER BY messages_raw.author_id, seq",
+        )?;
+
+        let rows = stmt
+            .query_map(NO_PARAMS, |row| (row.get(0), row.get(1)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+
+        //Messages are in seq order so a later message about the same contact overwrites an earlier one.
+        let mut latest = HashMap::<String, HashMap<String, bool>>::new();
+        for (author, content) in rows {
+            let content: Value = serde_json::from_str(&content)?;
+
+            if let Some(contact) = contact_of(&content) {
+                let following = content["following"].as_bool().unwrap_or(false);
+                let blocking = content["blocking"].as_bool().unwrap_or(false);
+                latest
+                    .entry(author)
+                    .or_insert_with(HashMap::new)
+                    .insert(contact, following && !blocking);
+            }
+        }
+
+        let graph = latest
+            .into_iter()
+            .map(|(author, contacts)| {
+                let mut edges = contacts.into_iter().collect::<Vec<_>>();
+                edges.sort();
+                (author, edges)
+            })
+            .collect();
+
+        Ok(graph)
+    }
+
+    /// Content of the most recent about message `author` published about themselves.
+    pub fn get_latest_about_for_author(&mut self, author: &str) -> Result<Option<Value>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT messages_raw.content FROM abouts_raw JOIN messages_raw ON messages_raw.key_id=abouts_raw.link_from_key_id WHERE abouts_raw.link_to_author_id=(SELECT id FROM authors WHERE author=?1) AND messages_raw.author_id=abouts_raw.link_to_author_id AND messages_raw.content_type='about' ORDER BY messages_raw.seq DESC LIMIT 1",
+        )?;
+
+        let content: Option<String> = match stmt.query_row(&[author], |row| row.get(0)) {
+            Ok(content) => content,
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        content
+            .map(|content| serde_json::from_str(&content))
+            .transpose()
+            .map_err(|err| err.into())
+    }
+
+    pub fn get_message_count_per_author(&mut self) -> Result<HashMap<String, i64>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT authors.author, COUNT(messages_raw.flume_seq) FROM messages_raw JOIN authors ON messages_raw.author_id=authors.id GROUP BY messages_raw.author_id",
+        )?;
+
+        let rows = stmt.query_map(NO_PARAMS, |row| (row.get(0), row.get(1)))?;
+
+        let counts = rows.fold(HashMap::<String, i64>::new(), |mut map, row| {
+            let (author, count) = row.unwrap();
+            map.insert(author, count);
+            map
+        });
+
+        Ok(counts)
+    }
+
+    pub fn get_latest_sequence_for_author(&mut self, author: &str) -> Result<Option<u32>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT MAX(seq) FROM messages_raw WHERE author_id=(SELECT id FROM authors WHERE author=?1)",
+        )?;
+
+        stmt.query_row(&[author], |row| row.get(0))
+            .map_err(|err| err.into())
+    }
+
+    pub fn get_links_from_message(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT link_to_key FROM links WHERE link_from_key=?1")?;
+
+        let rows = stmt.query_map(&[key], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    pub fn get_links_to_message(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT link_from_key FROM links WHERE link_to_key=?1")?;
+
+        let rows = stmt.query_map(&[key], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// (from, to) for every link in a message `author` published.
+    pub fn get_link_graph_for_author(
+        &mut self,
+        author: &str,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT links.link_from_key, links.link_to_key FROM links JOIN messages_raw ON messages_raw.key_id=links.link_from_key_id WHERE messages_raw.author_id=(SELECT id FROM authors WHERE author=?1) ORDER BY messages_raw.flume_seq, links.id",
+        )?;
+
+        let rows = stmt.query_map(&[author], |row| (row.get(0), row.get(1)))?;
+
+        let links = rows.fold(Vec::<(String, String)>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(links)
+    }
+
+    /// (key, number of authors linking to it) for messages linked to by at least
+    /// `min_unique_authors` different authors, most widely linked first. The messages
+    /// themselves don't have to be stored.
+    pub fn get_hub_messages(
+        &mut self,
+        min_unique_authors: i64,
+        limit: i64,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key, COUNT(DISTINCT messages_raw.author_id) AS unique_authors FROM links_raw
+            JOIN messages_raw ON messages_raw.key_id=links_raw.link_from_key_id
+            JOIN keys ON keys.id=links_raw.link_to_key_id
+            GROUP BY links_raw.link_to_key_id
+            HAVING unique_authors >= ?1
+            ORDER BY unique_authors DESC, keys.key ASC LIMIT ?2",
+        )?;
+
+        let hubs = stmt
+            .query_map(&[&min_unique_authors, &limit], |row| (row.get(0), row.get(1)))?
+            .collect::<Result<Vec<(String, i64)>, _>>()?;
+
+        Ok(hubs)
+    }
+
+    /// (key, number of links to it) for the most linked to messages, most linked first.
+    pub fn get_most_linked_messages(&mut self, limit: i64) -> Result<Vec<(String, i64)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key, COUNT(links_raw.id) AS link_count FROM messages_raw
+            JOIN keys ON keys.id=messages_raw.key_id
+            LEFT JOIN links_raw ON links_raw.link_to_key_id=messages_raw.key_id
+            GROUP BY messages_raw.flume_seq
+            ORDER BY link_count DESC, messages_raw.flume_seq ASC LIMIT ?1",
+        )?;
+
+        let rows = stmt.query_map(&[&limit], |row| (row.get(0), row.get(1)))?;
+
+        let messages = rows.fold(Vec::<(String, i64)>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(messages)
+    }
+
+    /// Message keys that are linked to but that we don't have, eg. after an incomplete sync.
+    pub fn get_dangling_links(&mut self) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT DISTINCT links.link_to_key FROM links LEFT JOIN messages_raw ON messages_raw.key_id=links.link_to_key_id WHERE messages_raw.key_id IS NULL ORDER BY links.link_to_key",
+        )?;
+
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Blob refs (`&...sha256`) linked from the message with `key`.
+    pub fn get_blobs_referenced_by_message(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT link_to_blob FROM blob_links WHERE link_from_key=?1")?;
+
+        let rows = stmt.query_map(&[key], |row| row.get(0))?;
+
+        let blobs = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(blobs)
+    }
+
+    pub fn get_vote_count_for_message(&mut self, key: &str) -> Result<i64, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT COUNT(*) FROM links_raw JOIN messages_raw ON messages_raw.key_id=links_raw.link_from_key_id WHERE links_raw.link_to_key_id=(SELECT id FROM keys WHERE key=?1) AND messages_raw.content_type='vote'",
+        )?;
+
+        stmt.query_row(&[key], |row| row.get(0))
+            .map_err(|err| err.into())
+    }
+
+    /// Number of votes on the message with `key`, keyed by vote value (usually 1 or -1).
+    pub fn get_votes_breakdown_for_message(
+        &mut self,
+        key: &str,
+    ) -> Result<HashMap<i64, i64>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT json_extract(messages_raw.content, '$.vote.value') AS value, COUNT(*) FROM links_raw JOIN messages_raw ON messages_raw.key_id=links_raw.link_from_key_id WHERE links_raw.link_to_key_id=(SELECT id FROM keys WHERE key=?1) AND messages_raw.content_type='vote' AND value IS NOT NULL GROUP BY value",
+        )?;
+
+        let rows = stmt.query_map(&[key], |row| (row.get(0), row.get(1)))?;
+
+        let votes = rows.fold(HashMap::<i64, i64>::new(), |mut map, row| {
+            let (value, count) = row.unwrap();
+            map.insert(value, count);
+            map
+        });
+
+        Ok(votes)
+    }
+
+    /// Keys of messages whose mentions include the `target` author.
+    pub fn get_mentions_of(&mut self, target: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT link_from FROM mentions WHERE link_to=?1 ORDER BY flume_seq")?;
+
+        let rows = stmt.query_map(&[target], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Keys of messages that mention `author` or are contact messages about them, in log order.
+    pub fn get_messages_referencing_author(&mut self, author: &str) -> Result<Vec<String>, Error> {
+        let mut referencing = self
+            .connection
+            .prepare_cached("SELECT flume_seq, link_from FROM mentions WHERE link_to=?1")?
+            .query_map(&[author], |row| (row.get(0), row.get(1)))?
+            .collect::<Result<Vec<(i64, String)>, _>>()?;
+
+        //The LIKE only narrows down the rows to parse, the contact field is checked below.
+        let uri_term = uri_search_term(author);
+        let contacts = self
+            .connection
+            .prepare_cached(
+                "SELECT flume_seq, key, content FROM messages WHERE content_type='contact' AND (content LIKE '%' || ?1 || '%' OR content LIKE '%' || ?2 || '%')",
+            )?
+            .query_map(&[author, uri_term.as_str()], |row| {
+                (row.get(0), row.get(1), row.get(2))
+            })?
+            .collect::<Result<Vec<(i64, String, String)>, _>>()?;
+
+        for (flume_seq, key, content) in contacts {
+            let content: Value = serde_json::from_str(&content)?;
+            if contact_of(&content).as_ref().map(String::as_str) == Some(author) {
+                referencing.push((flume_seq, key));
+            }
+        }
+
+        referencing.sort();
+        referencing.dedup();
+
+        Ok(referencing.into_iter().map(|(_, key)| key).collect())
+    }
+
+    /// Keys of messages whose text matches an fts5 `query`, best matches first.
+    #[cfg(feature = "fts")]
+    pub fn search_messages(&mut self, query: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM messages_fts
+            JOIN messages_raw ON messages_raw.flume_seq=messages_fts.rowid
+            JOIN keys ON keys.id=messages_raw.key_id
+            WHERE messages_fts MATCH ?1 ORDER BY rank",
+        )?;
+
+        let rows = stmt.query_map(&[query], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Checks the author's sequence numbers run 1, 2, 3... with no gaps or duplicates.
+    /// Errors with `FlumeViewSqlError::FeedGap` at the first problem.
+    pub fn check_feed_integrity(&mut self, author: &str) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT seq FROM messages_raw WHERE author_id=(SELECT id FROM authors WHERE author=?1) ORDER BY seq ASC",
+        )?;
+
+        let seqs = stmt
+            .query_map(&[author], |row| row.get(0))?
+            .collect::<Result<Vec<u32>, _>>()?;
+
+        for (expected, found) in (1..).zip(seqs) {
+            if found != expected {
+                return Err(FlumeViewSqlError::FeedGap {
+                    author: author.to_string(),
+                    expected,
+                    found,
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks each message's previous is the key of the message one sequence before it. Errors
+    /// with `FlumeViewSqlError::BrokenHashChain` at the first mismatch. Gaps in the feed are left
+    /// to `check_feed_integrity`.
+    pub fn verify_hash_chain_for_author(&mut self, author: &str) -> Result<(), Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT seq, keys.key, previous FROM messages_raw JOIN keys ON keys.id=messages_raw.key_id WHERE author_id=(SELECT id FROM authors WHERE author=?1) ORDER BY seq ASC",
+        )?;
+
+        let messages = stmt
+            .query_map(&[author], |row| (row.get(0), row.get(1), row.get(2)))?
+            .collect::<Result<Vec<(u32, String, Option<String>)>, _>>()?;
+
+        for (before, after) in messages.iter().zip(messages.iter().skip(1)) {
+            let (before_seq, ref before_key, _) = *before;
+            let (after_seq, _, ref previous) = *after;
+
+            if after_seq == before_seq + 1 && previous.as_ref() != Some(before_key) {
+                return Err(FlumeViewSqlError::BrokenHashChain { at_seq: after_seq }.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inclusive (start, end) ranges of sequence numbers missing from the author's feed, eg.
+    /// after partial replication. Gaps after the latest stored message can't be known.
+    pub fn get_sequence_gaps_for_author(&mut self, author: &str) -> Result<Vec<(u32, u32)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT seq FROM messages_raw WHERE author_id=(SELECT id FROM authors WHERE author=?1) ORDER BY seq ASC",
+        )?;
+
+        let seqs = stmt
+            .query_map(&[author], |row| row.get(0))?
+            .collect::<Result<Vec<u32>, _>>()?;
+
+        let mut gaps = Vec::new();
+        let mut next = 1;
+        for seq in seqs {
+            if seq > next {
+                gaps.push((next, seq - 1));
+            }
+            //Duplicates don't move us forward.
+            next = std::cmp::max(next, seq + 1);
+        }
+
+        Ok(gaps)
+    }
+
+    /// Keys of messages of `content_type` that start a thread, newest first.
+    pub fn get_root_messages(
+        &mut self,
+        content_type: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE content_type=?1 AND root_id IS NULL ORDER BY asserted_time DESC LIMIT ?2 OFFSET ?3",
+        )?;
+
+        let rows = stmt.query_map(&[&content_type as &ToSql, &limit, &offset], |row| {
+            row.get(0)
+        })?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    pub fn get_thread_by_root(&mut self, root: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(THREAD_BY_ROOT_QUERY)?;
+
+        let rows = stmt.query_map(&[root], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Number of distinct authors in a thread, counting whoever wrote the root.
+    pub fn get_thread_participant_count(&mut self, root: &str) -> Result<i64, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT COUNT(DISTINCT author_id) FROM messages_raw WHERE root_id=(SELECT id FROM keys WHERE key=?1) OR key_id=(SELECT id FROM keys WHERE key=?1)",
+        )?;
+
+        stmt.query_row(&[root], |row| row.get(0))
+            .map_err(|err| err.into())
+    }
+
+    /// (fork, key) pairs for the messages in `root`'s thread that fork off another message, in
+    /// sequence order.
+    pub fn get_forks_for_root(&mut self, root: &str) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT fork, key FROM messages WHERE root_id=(SELECT id FROM keys WHERE key=?1) AND fork IS NOT NULL ORDER BY seq",
+        )?;
+
+        let forks = stmt
+            .query_map(&[root], |row| (row.get(0), row.get(1)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+
+        Ok(forks)
+    }
+
+    /// Keys of messages that name `key` as their `branch`. Some clients write `branch` as an array
+    /// of keys; a reply is found through any of them.
+    pub fn get_replies_to(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM branches_raw
+            JOIN messages_raw ON messages_raw.key_id=branches_raw.link_from_key_id
+            JOIN keys ON keys.id=messages_raw.key_id
+            WHERE branches_raw.link_to_key_id=(SELECT id FROM keys WHERE key=?1)
+            ORDER BY messages_raw.seq ASC",
+        )?;
+
+        let rows = stmt.query_map(&[key], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Keys of the messages `key` replies to through its root and branches, and theirs in turn, up
+    /// to `max_depth` steps away. Nearest first.
+    pub fn get_causal_ancestors(&mut self, key: &str, max_depth: u32) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(CAUSAL_ANCESTORS_QUERY)?;
+
+        let keys = stmt
+            .query_map(&[&key as &ToSql, &max_depth], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(keys)
+    }
+
+    /// Keys of messages received strictly after `since`, oldest first.
+    pub fn get_messages_since_received_time(
+        &mut self,
+        since: f64,
+        limit: i64,
+    ) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT keys.key FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE received_time > ?1 ORDER BY received_time ASC LIMIT ?2",
+        )?;
+
+        let rows = stmt.query_map(&[&since as &ToSql, &limit], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Keys of the newest messages by asserted time. Messages without one sort last.
+    pub fn get_recent_messages(&mut self, limit: i64) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT key FROM messages WHERE content_type IS NOT NULL ORDER BY asserted_time IS NULL, asserted_time DESC LIMIT ?1",
+        )?;
+
+        let rows = stmt.query_map(&[&limit], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// Keys of messages asserted between `start_ts` and `end_ts` inclusive, oldest first.
+    pub fn get_messages_by_time_range(
+        &mut self,
+        start_ts: f64,
+        end_ts: f64,
+        limit: i64,
+    ) -> Result<Vec<String>, Error> {
+        check_time_range(start_ts, end_ts)?;
+
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT key FROM messages WHERE asserted_time >= ?1 AND asserted_time <= ?2 ORDER BY asserted_time ASC LIMIT ?3",
+        )?;
+
+        let rows = stmt.query_map(&[&start_ts as &ToSql, &end_ts, &limit], |row| row.get(0))?;
+
+        let keys = rows.fold(Vec::<String>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(keys)
+    }
+
+    /// How many messages `get_messages_by_time_range` would find without a limit.
+    pub fn get_message_count_in_time_range(
+        &mut self,
+        start_ts: f64,
+        end_ts: f64,
+    ) -> Result<i64, Error> {
+        check_time_range(start_ts, end_ts)?;
+
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT COUNT(*) FROM messages_raw WHERE asserted_time >= ?1 AND asserted_time <= ?2",
+        )?;
+
+        stmt.query_row(&[&start_ts, &end_ts], |row| row.get(0))
+            .map_err(|err| err.into())
+    }
+
+    /// Key of the message with the earliest asserted time.
+    pub fn get_oldest_message(&mut self) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT key FROM messages ORDER BY asserted_time ASC LIMIT 1")?;
+
+        match stmt.query_row(NO_PARAMS, |row| row.get(0)) {
+            Ok(key) => Ok(Some(key)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Key of the message with the latest asserted time.
+    pub fn get_newest_message(&mut self) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT key FROM messages ORDER BY asserted_time DESC LIMIT 1")?;
+
+        match stmt.query_row(NO_PARAMS, |row| row.get(0)) {
+            Ok(key) => Ok(Some(key)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Flume sequences of the messages in a thread that no other message builds on.
+    pub fn get_current_heads(&mut self, root: &str) -> Result<Vec<i64>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT flume_seq FROM heads_raw JOIN messages_raw ON messages_raw.key_id=heads_raw.key_id WHERE heads_raw.root_id=(SELECT id FROM keys WHERE key=?1) ORDER BY flume_seq",
+        )?;
+
+        let rows = stmt.query_map(&[root], |row| row.get(0))?;
+
+        let seqs = rows.fold(Vec::<i64>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(seqs)
+    }
+
+    /// (flume_seq, key) pairs for every message with `start <= flume_seq <= end`.
+    pub fn get_messages_in_flume_seq_range(
+        &mut self,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<(i64, String)>, Error> {
+        let mut stmt = self.connection.prepare_cached(
+            "SELECT flume_seq, keys.key FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE flume_seq >= ?1 AND flume_seq <= ?2 ORDER BY flume_seq",
+        )?;
+
+        let rows = stmt.query_map(&[&start, &end], |row| (row.get(0), row.get(1)))?;
+
+        let messages = rows.fold(Vec::<(i64, String)>::new(), |mut vec, row| {
+            vec.push(row.unwrap());
+            vec
+        });
+
+        Ok(messages)
+    }
+
+    /// Like `FlumeView::append`, but reports a failure instead of logging and skipping it.
+    pub fn append_with_result(&mut self, seq: Sequence, item: &[u8]) -> Result<(), Error> {
+        self.check_writable()?;
+        let key = append_item(
+            &self.connection,
+            &self.secret_keys,
+            self.max_message_size,
+            self.content_type_allowlist.as_ref().map(Vec::as_slice),
+            seq,
+            item,
+        )?;
+
+        self.remember_keys(&[key]);
+        Ok(())
+    }
+
+    /// Like `append_with_result`, but returns the flume_seq the message was stored under.
+    pub fn append_and_get_seq(&mut self, seq: Sequence, item: &[u8]) -> Result<i64, Error> {
+        self.check_writable()?;
+        let tx = self.connection.transaction()?;
+
+        let key = append_item(
+            &tx,
+            &self.secret_keys,
+            self.max_message_size,
+            self.content_type_allowlist.as_ref().map(Vec::as_slice),
+            seq,
+            item,
+        )?;
+
+        //last_insert_rowid() would be no use here since the links, heads and so on are inserted
+        //after the message row, so read back the row stored for the key.
+        let flume_seq = tx.query_row(
+            "SELECT flume_seq FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key=?1",
+            &[&key],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
+
+        self.remember_keys(&[key]);
+        Ok(flume_seq)
+    }
+
+    /// Like `append_with_result`, but a message whose key is already stored is skipped rather
+    /// than reported. Returns whether the message was inserted.
+    pub fn append_if_not_exists(&mut self, seq: Sequence, item: &[u8]) -> Result<bool, Error> {
+        self.check_writable()?;
+        let tx = self.connection.transaction()?;
+
+        //Dropping the transaction rolls back anything looked up before the duplicate was found.
+        let result = append_item(
+            &tx,
+            &self.secret_keys,
+            self.max_message_size,
+            self.content_type_allowlist.as_ref().map(Vec::as_slice),
+            seq,
+            item,
+        );
+        let key = match result {
+            Ok(key) => key,
+            Err(err) => {
+                return match err.downcast::<FlumeViewSqlError>() {
+                    Ok(FlumeViewSqlError::DuplicateKey { .. }) => Ok(false),
+                    Ok(err) => Err(err.into()),
+                    Err(err) => Err(err),
+                };
+            }
+        };
+
+        tx.commit()?;
+        self.remember_keys(&[key]);
+        Ok(true)
+    }
+
+    /// Appends every item in one transaction and returns what happened to each, in order. Items
+    /// that fail to append are rolled back on their own and reported as `AppendResult::Skipped`;
+    /// the rest are still stored. Only database errors fail the whole batch.
+    ///
+    /// With `FlumeViewSqlBuilder::batch_chunk_size` set, each chunk is its own transaction
+    /// instead.
+    pub fn append_batch(
+        &mut self,
+        items: &[(Sequence, Vec<u8>)],
+    ) -> Result<Vec<AppendResult>, Error> {
+        self.check_writable()?;
+
+        let chunk_size = self.batch_chunk_size.unwrap_or(items.len()).max(1);
+        let mut results = Vec::with_capacity(items.len());
+        for chunk in items.chunks(chunk_size) {
+            results.extend(self.append_chunk(chunk)?);
+        }
+
+        Ok(results)
+    }
+
+    /// Appends a batch that interleaves several feeds, eg. a replayed log. Each author's
+    /// messages are appended in sequence order, and any whose sequence isn't past the author's
+    /// latest is skipped. Items that can't be parsed or fail to append are skipped too, with a
+    /// warning. Returns the sequences that were stored, in ascending order.
+    pub fn append_batch_multi_feed(
+        &mut self,
+        items: Vec<(Sequence, Vec<u8>)>,
+    ) -> Result<Vec<Sequence>, Error> {
+        self.check_writable()?;
+
+        //Authors stay in the order they first appear so the result doesn't depend on hashing.
+        let mut authors = Vec::<String>::new();
+        let mut feeds = HashMap::<String, Vec<(u32, Sequence, Vec<u8>)>>::new();
+        for (seq, item) in items {
+            if self.max_message_size.map_or(false, |limit| item.len() > limit) {
+                warn!("Skipping item at seq {}, it is {} bytes", seq, item.len());
+                continue;
+            }
+
+            let message = match SsbMessage::from_bytes(&item) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("Skipping item at seq {}, failed to parse: {}", seq, err);
+                    continue;
+                }
+            };
+
+            if !feeds.contains_key(&message.value.author) {
+                authors.push(message.value.author.clone());
+            }
+            feeds
+                .entry(message.value.author)
+                .or_insert_with(Vec::new)
+                .push((message.value.sequence, seq, item));
+        }
+
+        let mut tx = self.connection.transaction()?;
+        let mut accepted = Vec::new();
+        let mut keys = Vec::new();
+
+        for author in authors {
+            let mut feed = feeds.remove(&author).unwrap_or_default();
+            feed.sort_by_key(|entry| entry.0);
+
+            let mut latest: Option<u32> = tx.query_row(
+                "SELECT MAX(seq) FROM messages_raw WHERE author_id=(SELECT id FROM authors WHERE author=?1)",
+                &[&author],
+                |row| row.get(0),
+            )?;
+
+            for (sequence, seq, item) in feed {
+                if latest.map_or(false, |latest| sequence <= latest) {
+                    warn!(
+                        "Skipping item at seq {}, {} already has sequence {}",
+                        seq, author, sequence
+                    );
+                    continue;
+                }
+
+                //A savepoint per item so a failure only rolls back that item.
+                let savepoint = tx.savepoint()?;
+                let result = append_item(
+                    &savepoint,
+                    &self.secret_keys,
+                    self.max_message_size,
+                    self.content_type_allowlist.as_ref().map(Vec::as_slice),
+                    seq,
+                    &item,
+                );
+
+                match result {
+                    Ok(key) => {
+                        savepoint.commit()?;
+                        latest = Some(sequence);
+                        accepted.push(seq);
+                        keys.push(key);
+                    }
+                    Err(err) => warn!("Skipping item at seq {}, failed to append: {}", seq, err),
+                }
+            }
+        }
+
+        tx.commit()?;
+        self.remember_keys(&keys);
+
+        accepted.sort();
+        Ok(accepted)
+    }
+
+    fn append_chunk(
+        &mut self,
+        items: &[(Sequence, Vec<u8>)],
+    ) -> Result<Vec<AppendResult>, Error> {
+        trace!("Start batch append");
+        let mut tx = self.connection.transaction()?;
+
+        let mut keys = Vec::with_capacity(items.len());
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            //A savepoint per item so a failure only rolls back that item.
+            let savepoint = tx.savepoint()?;
+            let result = append_item(
+                &savepoint,
+                &self.secret_keys,
+                self.max_message_size,
+                self.content_type_allowlist.as_ref().map(Vec::as_slice),
+                item.0,
+                &item.1,
+            );
+
+            match result {
+                Ok(key) => {
+                    savepoint.commit()?;
+                    keys.push(key);
+                    results.push(AppendResult::Ok(item.0));
+                }
+                Err(err) => {
+                    warn!("Skipping item at seq {}, failed to append: {}", item.0, err);
+                    results.push(AppendResult::Skipped(item.0, err.to_string()));
+                }
+            }
+        }
+
+        tx.commit()?;
+        self.remember_keys(&keys);
+        Ok(results)
+    }
+
+    /// Adds a secret key and re-processes stored private messages with it.
+    /// Returns how many messages the new key decrypted.
+    pub fn add_key(&mut self, secret_key: SecretKey) -> Result<usize, Error> {
+        self.check_writable()?;
+        self.secret_keys.push(secret_key);
+        let new_key = &self.secret_keys[self.secret_keys.len() - 1..];
+
+        let tx = self.connection.transaction()?;
+
+        let undecrypted = tx
+            .prepare(
+                "SELECT flume_seq, key, author, seq, received_time, asserted_time, raw_content,
+                previous
+                FROM messages_raw
+                JOIN keys ON keys.id=messages_raw.key_id
+                JOIN authors ON authors.id=messages_raw.author_id
+                WHERE is_decrypted=0 AND content IS NULL AND raw_content IS NOT NULL",
+            )?
+            .query_map(NO_PARAMS, |row| {
+                let message = SsbMessage {
+                    key: row.get(1),
+                    value: SsbValue {
+                        previous: row.get(7),
+                        author: row.get(2),
+                        sequence: row.get(3),
+                        timestamp: row.get(5),
+                        content: Value::String(row.get(6)),
+                    },
+                    timestamp: row.get(4),
+                };
+                (row.get(0), message)
+            })?
+            .collect::<Result<Vec<(i64, SsbMessage)>, _>>()?;
+
+        let mut num_decrypted = 0;
+
+        //Nothing else was indexed for a message we couldn't read, so it can be re-appended from scratch.
+        for (flume_seq, message) in undecrypted {
+            tx.execute(
+                "DELETE FROM messages_raw WHERE flume_seq = ?1",
+                &[&flume_seq],
+            )?;
+
+            if append_message(&tx, new_key, flume_seq as Sequence, message)? {
+                num_decrypted += 1;
+            }
+        }
+
+        tx.commit()?;
+
+        //Undecrypted messages were deleted and appended again above.
+        self.reload_key_filter()?;
+        Ok(num_decrypted)
+    }
+
+    /// Prepares the statements used by appends and the hottest reads so the first append doesn't
+    /// pay for compiling them. The statements are returned to rusqlite's cache, not run.
+    pub fn warm_statement_cache(&self) -> Result<(), Error> {
+        for sql in WARM_STATEMENTS.iter() {
+            self.connection.prepare_cached(sql)?;
+        }
+
+        #[cfg(feature = "fts")]
+        self.connection.prepare_cached(INSERT_FTS)?;
+
+        Ok(())
+    }
+
+    /// Removes every message with a flume_seq after `seq`, and what was indexed from them, so the
+    /// log can be replayed from there. Returns how many messages were removed.
+    pub fn truncate_after(&mut self, seq: Sequence) -> Result<usize, Error> {
+        self.check_writable()?;
+        let tx = self.connection.transaction()?;
+        let seq = seq as i64;
+
+        let roots = tx
+            .prepare(
+                "SELECT DISTINCT root_id FROM messages_raw WHERE flume_seq > ?1 AND root_id IS NOT NULL",
+            )?
+            .query_map(&[&seq], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+
+        let truncated = "SELECT key_id FROM messages_raw WHERE flume_seq > ?1";
+        for table in &[
+            "links_raw",
+            "branches_raw",
+            "mentions_raw",
+            "blob_links_raw",
+            "abouts_raw",
+            "contacts_raw",
+        ] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE link_from_key_id IN ({})",
+                    table, truncated
+                ),
+                &[&seq],
+            )?;
+        }
+        #[cfg(feature = "fts")]
+        tx.execute("DELETE FROM messages_fts WHERE rowid > ?1", &[&seq])?;
+
+        let num_deleted = tx.execute("DELETE FROM messages_raw WHERE flume_seq > ?1", &[&seq])?;
+
+        //Messages that the removed ones built on can be heads again, so work them out afresh.
+        for root_id in roots {
+            tx.execute("DELETE FROM heads_raw WHERE root_id = ?1", &[&root_id])?;
+            tx.execute(
+                "INSERT INTO heads_raw (root_id, key_id)
+                SELECT root_id, key_id FROM messages_raw
+                WHERE root_id = ?1 AND key_id NOT IN (SELECT link_to_key_id FROM branches_raw)",
+                &[&root_id],
+            )?;
+        }
+
+        tx.commit()?;
+
+        //A bloom filter can't forget keys, so build a new one without the removed messages.
+        self.reload_key_filter()?;
+        Ok(num_deleted)
+    }
+
+    /// Drops every table and view and creates them again empty, without closing the connection.
+    pub fn drop_all_tables(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        let tx = self.connection.transaction()?;
+
+        let me: Option<String> = match tx.query_row(
+            "SELECT author FROM authors WHERE is_me = 1",
+            NO_PARAMS,
+            |row| row.get(0),
+        ) {
+            Ok(me) => Some(me),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        drop_schema(&tx)?;
+
+        create_tables(&tx)?;
+        create_indices(&tx)?;
+        create_views(&tx)?;
+        #[cfg(feature = "fts")]
+        create_fts_tables(&tx)?;
+
+        set_db_version(&tx)?;
+        set_schema_version(&tx, CURRENT_SCHEMA_VERSION)?;
+        if let Some(me) = me {
+            set_author_that_is_me(&tx, &me)?;
+        }
+
+        tx.commit()?;
+
+        self.reload_key_filter()
+    }
+
+    /// Creates the indices skipped by `FlumeViewSqlBuilder::defer_index_creation`.
+    pub fn finalize_import(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        let tx = self.connection.transaction()?;
+
+        create_indices(&tx)?;
+
+        tx.commit().map_err(|err| err.into())
+    }
+
+    pub fn rebuild_indices(&mut self) -> Result<(), Error> {
+        self.rebuild_indices_with_progress(|_| ())
+    }
+
+    /// Like `rebuild_indices`, but calls `callback` before and after each table's indices are
+    /// created.
+    pub fn rebuild_indices_with_progress<F>(&mut self, callback: F) -> Result<(), Error>
+    where
+        F: Fn(IndexProgress),
+    {
+        self.check_writable()?;
+        let tx = self.connection.transaction()?;
+
+        drop_indices(&tx)?;
+        create_indices_with_progress(&tx, callback)?;
+
+        tx.commit().map_err(|err| err.into())
+    }
+
+    /// Row counts and on disk size, for a quick overview of the db.
+    pub fn get_stats(&mut self) -> Result<DbStats, Error> {
+        let count = |connection: &Connection, sql: &str| -> Result<i64, rusqlite::Error> {
+            connection.query_row(sql, NO_PARAMS, |row| row.get(0))
+        };
+
+        let message_count = count(&self.connection, "SELECT COUNT(*) FROM messages_raw")?;
+        let author_count = count(&self.connection, "SELECT COUNT(*) FROM authors")?;
+        let link_count = count(&self.connection, "SELECT COUNT(*) FROM links_raw")?;
+        let page_count = count(&self.connection, "PRAGMA page_count")?;
+        let page_size = count(&self.connection, "PRAGMA page_size")?;
+
+        Ok(DbStats {
+            message_count,
+            author_count,
+            link_count,
+            db_size_bytes: (page_count * page_size) as u64,
+        })
+    }
+
+    /// Copies the write-ahead log back into the db. Returns (frames in the wal, frames
+    /// checkpointed), both -1 when the db isn't in WAL mode.
+    pub fn wal_checkpoint(&mut self, mode: WalCheckpointMode) -> Result<(i64, i64), Error> {
+        self.connection
+            .query_row(
+                &format!("PRAGMA wal_checkpoint({})", mode.as_str()),
+                NO_PARAMS,
+                |row| (row.get(1), row.get(2)),
+            )
+            .map_err(|err| err.into())
+    }
+
+    /// sqlite's `PRAGMA user_version`. Migrations keep it equal to the schema version.
+    pub fn get_user_version(&self) -> Result<i32, Error> {
+        get_user_version(&self.connection).map_err(|err| err.into())
+    }
+
+    pub fn set_user_version(&mut self, version: i32) -> Result<(), Error> {
+        self.check_writable()?;
+        set_user_version(&self.connection, version)?;
+        Ok(())
+    }
+
+    pub fn vacuum(&mut self) -> Result<(), Error> {
+        self.connection
+            .execute("VACUUM", NO_PARAMS)
+            .map(|_| ())
+            .map_err(|err| err.into())
+    }
+
+    /// Opens another db on this connection under `alias`, so queries can read from it, eg.
+    /// the view of another identity's log.
+    pub fn attach_database(&mut self, path: &str, alias: &str) -> Result<(), Error> {
+        check_database_alias(alias)?;
+        self.connection
+            .execute("ATTACH DATABASE ?1 AS ?2", &[path, alias])
+            .map(|_| ())
+            .map_err(|err| err.into())
+    }
+
+    pub fn detach_database(&mut self, alias: &str) -> Result<(), Error> {
+        self.connection
+            .execute("DETACH DATABASE ?1", &[alias])
+            .map(|_| ())
+            .map_err(|err| err.into())
+    }
+
+    /// Writes a compacted copy of the db to `path`, leaving the live db untouched.
+    //The bundled sqlite predates VACUUM INTO (3.27), so back up to `path` and vacuum the copy.
+    pub fn vacuum_into(&mut self, path: &str) -> Result<(), Error> {
+        self.connection.backup(DatabaseName::Main, path, None)?;
+
+        Connection::open(path)?
+            .execute("VACUUM", NO_PARAMS)
+            .map(|_| ())
+            .map_err(|err| err.into())
+    }
+
+    /// Writes every stored message to `writer` as one JSON object per line, in flume seq order.
+    /// Returns how many messages were written.
+    pub fn export_to_ndjson<W: std::io::Write>(&mut self, writer: &mut W) -> Result<usize, Error> {
+        let mut stmt = self.connection.prepare(EXPORT_QUERY)?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        let mut count = 0;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let content: Option<String> = row.get_checked(9)?;
+            let content = match content {
+                Some(content) => serde_json::from_str(&content)?,
+                None => Value::Null,
+            };
+
+            let line = json!({
+                "flume_seq": row.get_checked::<_, i64>(0)?,
+                "key": row.get_checked::<_, String>(1)?,
+                "author": row.get_checked::<_, String>(2)?,
+                "seq": row.get_checked::<_, i64>(3)?,
+                "received_time": row.get_checked::<_, Option<f64>>(4)?,
+                "asserted_time": row.get_checked::<_, Option<f64>>(5)?,
+                "root": row.get_checked::<_, Option<String>>(6)?,
+                "fork": row.get_checked::<_, Option<String>>(7)?,
+                "content_type": row.get_checked::<_, Option<String>>(8)?,
+                "content": content,
+                "is_decrypted": row.get_checked::<_, Option<bool>>(10)?,
+                "raw_content": row.get_checked::<_, Option<String>>(11)?,
+                "channel": row.get_checked::<_, Option<String>>(12)?,
+                "decryption_result": row.get_checked::<_, Option<String>>(13)?,
+                "previous": row.get_checked::<_, Option<String>>(14)?,
+            });
+
+            serde_json::to_writer(&mut *writer, &line)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Copies the db to `dest` a few pages at a time, so appends can carry on while it runs.
+    pub fn clone_to_path(&self, dest: &str, progress: Option<fn(Progress)>) -> Result<(), Error> {
+        self.connection
+            .backup(DatabaseName::Main, dest, progress)
+            .map_err(|err| err.into())
+    }
+
+    pub fn check_db_integrity(&mut self) -> Result<(), Error> {
+        self.check_integrity_detailed().map(|_| ())
+    }
+
+    /// Returns an empty Vec for a healthy db. Otherwise errors with every problem sqlite found.
+    pub fn check_integrity_detailed(&mut self) -> Result<Vec<String>, Error> {
+        let mut stmt = self.connection.prepare("PRAGMA integrity_check")?;
+
+        let details = stmt
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        if details.len() == 1 && details[0] == "ok" {
+            return Ok(Vec::new());
+        }
+
+        Err(FlumeViewSqlError::DbFailedIntegrityCheck { details }.into())
+    }
+
+    pub fn get_latest(&self) -> Result<Sequence, Error> {
+        let mut stmt = self.connection.prepare_cached(LATEST_FLUME_SEQ_QUERY)?;
+
+        stmt.query_row(NO_PARAMS, |row| {
+            let res: i64 = row.get_checked(0).unwrap_or(0);
+            trace!("got latest seq from db: {}", res);
+            res as Sequence
+        })
+        .map_err(|err| err.into())
+    }
+}
+
+//Aliases end up in SQL text since sqlite can't bind a schema name, so only plain names are let through.
+fn check_database_alias(alias: &str) -> Result<(), Error> {
+    let is_plain_name = !alias.is_empty()
+        && !alias.starts_with(|c: char| c.is_ascii_digit())
+        && alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !is_plain_name {
+        return Err(FlumeViewSqlError::InvalidDatabaseAlias {
+            alias: alias.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn check_time_range(start: f64, end: f64) -> Result<(), Error> {
+    if start > end {
+        return Err(FlumeViewSqlError::InvalidTimeRange { start, end }.into());
+    }
+    Ok(())
+}
+
+fn ssb_message_from_row(row: &Row) -> Result<SsbMessage, Error> {
+    let content: Option<String> = row.get_checked(5)?;
+    let raw_content: Option<String> = row.get_checked(6)?;
+
+    //Private messages we couldn't decrypt are returned as the boxed string we received.
+    let content = match (content, raw_content) {
+        (Some(content), _) => serde_json::from_str(&content)?,
+        (None, Some(raw_content)) => Value::String(raw_content),
+        (None, None) => Value::Null,
+    };
+
+    Ok(SsbMessage {
+        key: row.get_checked(0)?,
+        value: SsbValue {
+            previous: row.get_checked(7)?,
+            author: row.get_checked(1)?,
+            sequence: row.get_checked(2)?,
+            timestamp: row.get_checked(3)?,
+            content,
+        },
+        timestamp: row.get_checked(4)?,
+    })
+}
+
+//Turns an `ssb:` URI into the classic sigil form so both spellings of a ref are stored the same.
+//Classic refs pass through untouched, URIs for things we don't know are None.
+fn normalize_ssb_ref(reference: &str) -> Option<String> {
+    if !reference.starts_with("ssb:") {
+        return Some(reference.to_string());
+    }
+
+    let mut parts = reference["ssb:".len()..].splitn(3, '/');
+    let (sigil, suffix) = match (parts.next()?, parts.next()?) {
+        ("message", "sha256") => ("%", "sha256"),
+        ("blob", "sha256") => ("&", "sha256"),
+        ("feed", "ed25519") => ("@", "ed25519"),
+        _ => return None,
+    };
+
+    //URIs use url safe base64 and may leave off the padding.
+    let mut data = parts.next()?.replace('-', "+").replace('_', "/");
+    while data.len() % 4 != 0 {
+        data.push('=');
+    }
+
+    Some(format!("{}{}.{}", sigil, data, suffix))
+}
+
+//The contact a contact message is about, in the classic form `contacts_raw` stores it in.
+fn contact_of(content: &Value) -> Option<String> {
+    content["contact"].as_str().and_then(normalize_ssb_ref)
+}
+
+//The base64 of a classic ref spelled the way its ssb: URI spells it, so a LIKE can find content
+//that uses either form. `_` is a LIKE wildcard, which only makes the match looser.
+fn uri_search_term(reference: &str) -> String {
+    let data = reference
+        .get(1..)
+        .and_then(|rest| rest.rsplitn(2, '.').nth(1))
+        .unwrap_or(reference);
+
+    data.trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn find_values_in_object_by_key<'a>(
+    obj: &'a serde_json::Value,
+    key: &str,
+    values: &mut Vec<&'a serde_json::Value>,
+) {
+    match obj.get(key) {
+        Some(val) => values.push(val),
+        _ => (),
+    };
+
+    match obj {
+        Value::Array(arr) => {
+            for val in arr {
+                find_values_in_object_by_key(val, key, values);
+            }
+        }
+        Value::Object(kv) => {
+            for val in kv.values() {
+                match val {
+                    Value::Object(_) => find_values_in_object_by_key(val, key, values),
+                    Value::Array(_) => find_values_in_object_by_key(val, key, values),
+                    _ => (),
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+fn is_private_box_suffix(suffix: &str) -> bool {
+    match suffix {
+        "box" => true,
+        #[cfg(feature = "box2")]
+        "box2" => true,
+        _ => false,
+    }
+}
+
+fn attempt_decryption(
+    mut message: SsbMessage,
+    secret_keys: &[SecretKey],
+) -> Result<(DecryptionResult, SsbMessage), Error> {
+    let mut decryption_result = DecryptionResult::NotEncrypted;
+
+    if let Value::Null = message.value.content["type"] {
+        let boxed = message.value.content.as_str().and_then(|content| {
+            let mut parts = content.rsplitn(2, '.');
+            let suffix = parts.next()?.to_string();
+            let cypher = decode(parts.next()?);
+            Some((suffix, cypher))
+        });
+
+        //Content that isn't a suffixed base64 string can't be a private box, leave it alone.
+        //Unless the suffix says it is, then the ciphertext is broken.
+        let boxed = match boxed {
+            Some((ref suffix, Err(_))) if is_private_box_suffix(suffix) => {
+                message.value.content = Value::Null;
+                return Ok((DecryptionResult::FailedInvalidCiphertext, message));
+            }
+            Some((suffix, Ok(cypher))) => Some((suffix, cypher)),
+            _ => None,
+        };
+
+        if let Some((suffix, cypher)) = boxed {
+            let data = match suffix.as_str() {
+                //The first key that successfully decrypts the message wins.
+                "box" => secret_keys
+                    .iter()
+                    .find_map(|secret_key| private_box::decrypt(&cypher, secret_key).ok()),
+                #[cfg(feature = "box2")]
+                "box2" => decrypt_box2(&cypher, secret_keys),
+                _ => return Err(FlumeViewSqlError::UnsupportedEncryption { suffix }.into()),
+            };
+
+            //If we can't decrypt it, throw it away.
+            let (result, content) = match data {
+                None => (DecryptionResult::FailedNoMatchingKey, Value::Null),
+                Some(data) => match serde_json::from_slice(&data) {
+                    Ok(content) => (DecryptionResult::Decrypted, content),
+                    Err(_) => (DecryptionResult::FailedInvalidJson, Value::Null),
+                },
+            };
+
+            decryption_result = result;
+            message.value.content = content;
+        }
+    }
+
+    Ok((decryption_result, message))
+}
+
+fn append_item(
+    connection: &Connection,
+    secret_keys: &[SecretKey],
+    max_message_size: Option<usize>,
+    content_type_allowlist: Option<&[String]>,
+    seq: Sequence,
+    item: &[u8],
+) -> Result<String, Error> {
+    //Checked before parsing so a huge item can't exhaust memory.
+    if let Some(limit) = max_message_size {
+        if item.len() > limit {
+            return Err(FlumeViewSqlError::MessageTooLarge {
+                size: item.len(),
+                limit,
+            }
+            .into());
+        }
+    }
+
+    let message = SsbMessage::from_bytes(item)?;
+
+    //Private messages have no type until they're decrypted, so they always get through.
+    if let (Some(allowlist), Some(content_type)) =
+        (content_type_allowlist, message.value.content["type"].as_str())
+    {
+        if !allowlist.iter().any(|allowed| allowed == content_type) {
+            return Err(FlumeViewSqlError::DisallowedContentType {
+                content_type: content_type.to_string(),
+            }
+            .into());
+        }
+    }
+
+    let key = message.key.clone();
+    append_message(connection, secret_keys, seq, message)?;
+
+    Ok(key)
+}
+
+//A bloom filter holding the key of every stored message.
+fn load_key_filter(connection: &Connection) -> Result<BloomFilter, Error> {
+    let count: i64 =
+        connection.query_row("SELECT COUNT(*) FROM messages_raw", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+    let capacity = ((count * 2) as u32).max(KEY_FILTER_MIN_CAPACITY);
+    let mut bloom = BloomFilter::with_rate(KEY_FILTER_FALSE_POSITIVE_RATE, capacity);
+
+    let mut stmt = connection
+        .prepare("SELECT keys.key FROM messages_raw JOIN keys ON keys.id=messages_raw.key_id")?;
+    let keys = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(0))?;
+    for key in keys {
+        bloom.insert(&key?);
+    }
+
+    Ok(bloom)
+}
+
+//Returns whether the message was private and one of our keys decrypted it.
+fn append_message(
+    connection: &Connection,
+    secret_keys: &[SecretKey],
+    seq: Sequence,
+    message: SsbMessage,
+) -> Result<bool, Error> {
+    //Public messages have object content, private ones are a boxed string.
+    let raw_content = match message.value.content {
+        Value::String(ref boxed) => Some(boxed.clone()),
+        _ => None,
+    };
+
+    let (decryption_result, message) = attempt_decryption(message, secret_keys)?;
+    let is_decrypted = decryption_result == DecryptionResult::Decrypted;
+
+    let message_key_id = find_or_create_key(&connection, &message.key)?;
+
+    let author_id = find_or_create_author(&connection, &message.value.author)?;
+    if is_sequence_taken(connection, author_id, message.value.sequence, message_key_id)? {
+        return Err(FlumeViewSqlError::DuplicateSequence {
+            author: message.value.author.clone(),
+            seq: message.value.sequence,
+        }
+        .into());
+    }
+
+    //The message row goes in first so a duplicate fails before anything else is indexed.
+    insert_message(
+        connection,
+        &message,
+        seq as i64,
+        message_key_id,
+        decryption_result,
+        raw_content.as_ref().map(|raw| raw.as_str()),
+    )
+    .map_err(|err| match err {
+        rusqlite::Error::SqliteFailure(ref failure, _)
+            if failure.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE =>
+        {
+            FlumeViewSqlError::DuplicateKey {
+                key: message.key.clone(),
+            }
+            .into()
+        }
+        err => Error::from(err),
+    })?;
+
+    let mut links = Vec::new();
+    find_values_in_object_by_key(&message.value.content, "link", &mut links);
+
+    insert_links(connection, links.as_slice(), message_key_id)?;
+    insert_mentions(connection, &message, message_key_id)?;
+    insert_blob_links(connection, links.as_slice(), message_key_id)?;
+
+    insert_branches(connection, &message, message_key_id)?;
+    insert_heads(connection, &message, message_key_id)?;
+    insert_or_update_contacts(connection, &message, message_key_id, is_decrypted)?;
+    insert_abouts(connection, &message, message_key_id)?;
+    #[cfg(feature = "fts")]
+    insert_fts(connection, &message, seq as i64)?;
+
+    Ok(is_decrypted)
+}
+
+fn create_tables(connection: &Connection) -> Result<(), Error> {
+    create_schema_version_tables(connection)?;
+    create_migrations_tables(connection)?;
+    create_messages_tables(connection)?;
+    create_authors_tables(connection)?;
+    create_keys_tables(connection)?;
+    create_links_tables(connection)?;
+    create_contacts_tables(connection)?;
+    create_branches_tables(connection)?;
+    create_mentions_tables(connection)?;
+    create_abouts_tables(connection)?;
+    create_blobs_tables(connection)?;
+    create_blob_links_tables(connection)?;
+    create_heads_tables(connection)?;
+
+    Ok(())
+}
+
+fn create_views(connection: &Connection) -> Result<(), Error> {
+    create_messages_views(connection)?;
+    create_links_views(connection)?;
+    create_blob_links_views(connection)?;
+    create_abouts_views(connection)?;
+    create_mentions_views(connection)?;
+    Ok(())
+}
+
+fn drop_indices(connection: &Connection) -> Result<(), Error> {
+    //Indices sqlite makes for UNIQUE constraints have no sql and can't be dropped.
+    let index_names = connection
+        .prepare("SELECT name FROM sqlite_master WHERE type='index' AND sql IS NOT NULL")?
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    for index_name in index_names {
+        trace!("Dropping index {}", index_name);
+        connection.execute(&format!("DROP INDEX IF EXISTS {}", index_name), NO_PARAMS)?;
+    }
+
+    Ok(())
+}
+
+type IndexCreator = fn(&Connection) -> Result<usize, rusqlite::Error>;
+
+const INDICES: [(&str, IndexCreator); 10] = [
+    ("messages", create_messages_indices),
+    ("links", create_links_indices),
+    ("blob_links", create_blob_links_indices),
+    ("contacts", create_contacts_indices),
+    ("keys", create_keys_indices),
+    ("branches", create_branches_indices),
+    ("authors", create_authors_indices),
+    ("abouts", create_abouts_indices),
+    ("mentions", create_mentions_indices),
+    ("heads", create_heads_indices),
+];
 
 fn create_indices(connection: &Connection) -> Result<(), Error> {
-    create_messages_indices(connection)?;
-    create_links_indices(connection)?;
-    create_blob_links_indices(connection)?;
-    create_contacts_indices(connection)?;
-    create_keys_indices(connection)?;
-    create_branches_indices(connection)?;
-    create_authors_indices(connection)?;
-    create_abouts_indices(connection)?;
-    create_mentions_indices(connection)?;
+    create_indices_with_progress(connection, |_| ())
+}
+
+//Reports before and after each table's indices are created, so `step` counts the ones done.
+fn create_indices_with_progress<F>(connection: &Connection, callback: F) -> Result<(), Error>
+where
+    F: Fn(IndexProgress),
+{
+    let total = INDICES.len() as u8;
+
+    for (step, &(index_name, create)) in INDICES.iter().enumerate() {
+        let step = step as u8;
+        callback(IndexProgress {
+            index_name,
+            step,
+            total,
+        });
+        create(connection)?;
+        callback(IndexProgress {
+            index_name,
+            step: step + 1,
+            total,
+        });
+    }
+
     Ok(())
 }
 
-#[cfg(test)]
-mod test {
-    use flume_view_sql::*;
-    use serde_json::*;
+#[cfg(test)]
+mod test {
+    use base64::encode;
+    use flume_view_sql::test_fixtures::*;
+    use flume_view_sql::*;
+    use serde_json::*;
+    use sodiumoxide::crypto::box_::gen_keypair;
+
+    #[test]
+    fn find_values_in_object() {
+        let obj = json!({ "key": 1, "value": {"link": "hello", "array": [{"link": "piet"}], "deeper": {"link": "world"}}});
+
+        let mut vec = Vec::new();
+        find_values_in_object_by_key(&obj, "link", &mut vec);
+
+        assert_eq!(vec.len(), 3);
+        assert_eq!(vec[0].as_str().unwrap(), "hello");
+        assert_eq!(vec[1].as_str().unwrap(), "piet");
+        assert_eq!(vec[2].as_str().unwrap(), "world");
+    }
+
+    #[test]
+    fn open_connection() {
+        let db = TempDb::new("open_connection");
+        let filename = db.path();
+        let keys = Vec::new();
+        FlumeViewSql::new(filename, keys, "").unwrap();
+        assert!(true)
+    }
+
+    #[test]
+    fn open_existing_rejects_missing_and_unknown_schemas() {
+        let db = TempDb::new("open_existing_rejects_missing_and_unknown_schemas");
+        let filename = db.path();
+
+        assert!(FlumeViewSql::open_existing(filename, Vec::new()).is_err());
+        assert!(!std::path::Path::new(filename).exists());
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        view.append(
+            1,
+            &message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"})),
+        );
+        drop(view);
+
+        let mut view = FlumeViewSql::open_existing(filename, Vec::new()).unwrap();
+        assert_eq!(view.get_latest().unwrap(), 1);
+        set_schema_version(&view.connection, CURRENT_SCHEMA_VERSION + 1).unwrap();
+        drop(view);
+
+        let err = FlumeViewSql::open_existing(filename, Vec::new())
+            .err()
+            .unwrap();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::UnrecognizedSchema { found, expected } => {
+                assert_eq!(found, CURRENT_SCHEMA_VERSION + 1);
+                assert_eq!(expected, CURRENT_SCHEMA_VERSION);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn find_or_create_author_is_stable() {
+        let view = make_test_view();
+
+        let id = find_or_create_author(&view.connection, AUTHOR).unwrap();
+        assert_eq!(find_or_create_author(&view.connection, AUTHOR).unwrap(), id);
+
+        let other_id = find_or_create_author(&view.connection, "@other=.ed25519").unwrap();
+        assert_ne!(other_id, id);
+        assert_eq!(find_or_create_author(&view.connection, AUTHOR).unwrap(), id);
+    }
+
+    #[test]
+    fn fixtures_cover_authors_and_types() {
+        let mut view = make_test_view();
+        let fixtures = [FIXTURE_POST, FIXTURE_CONTACT, FIXTURE_VOTE, FIXTURE_ENCRYPTED];
+        for (seq, fixture) in fixtures.iter().enumerate() {
+            view.append_with_result(seq as u64 + 1, fixture.as_bytes())
+                .unwrap();
+        }
+
+        assert_eq!(view.get_authors_list().unwrap().len(), 2);
+        assert_eq!(
+            view.get_all_content_types().unwrap(),
+            vec!["contact", "post", "vote"]
+        );
+        assert_eq!(view.get_encrypted_failed_messages(10, 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn normalize_ssb_refs() {
+        let classic = "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256";
+        assert_eq!(normalize_ssb_ref(classic).unwrap(), classic);
+        assert_eq!(
+            normalize_ssb_ref("ssb:message/sha256/KKPLj1tWfuVhCvgJz2hG_nIsVzmBRzUJaqHv-sb-n1c")
+                .unwrap(),
+            classic
+        );
+        assert_eq!(
+            normalize_ssb_ref("ssb:feed/ed25519/QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc-w=")
+                .unwrap(),
+            AUTHOR
+        );
+        assert!(normalize_ssb_ref("ssb:address/multiserver?x=y").is_none());
+    }
+
+    #[test]
+    fn uri_and_classic_links_are_stored_the_same() {
+        let mut view = make_test_view();
+        let target = "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256";
+        let uri = "ssb:message/sha256/KKPLj1tWfuVhCvgJz2hG_nIsVzmBRzUJaqHv-sb-n1c=";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%classic=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({"type": "post", "mentions": [{"link": target}]}),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%uri=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "mentions": [{"link": uri}]}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let distinct_targets: i64 = view
+            .connection
+            .query_row("SELECT COUNT(DISTINCT link_to_key) FROM links", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(distinct_targets, 1);
+        assert_eq!(
+            view.get_links_to_message(target).unwrap(),
+            vec!["%classic=.sha256", "%uri=.sha256"]
+        );
+    }
+
+    #[test]
+    fn uri_refs_are_stored_in_classic_form() {
+        let mut view = make_test_view();
+        let root = "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256";
+        let root_uri = "ssb:message/sha256/KKPLj1tWfuVhCvgJz2hG_nIsVzmBRzUJaqHv-sb-n1c=";
+        let blob = "&KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256";
+        let blob_uri = "ssb:blob/sha256/KKPLj1tWfuVhCvgJz2hG_nIsVzmBRzUJaqHv-sb-n1c";
+        let other_uri = "ssb:feed/ed25519/vzoU7_XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%reply=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({
+                        "type": "post",
+                        "root": root_uri,
+                        "fork": root_uri,
+                        "branch": root_uri,
+                        "mentions": [{"link": other_uri}, {"link": blob_uri}]
+                    }),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%follow=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "contact", "contact": other_uri, "following": true}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_thread_by_root(root).unwrap(), vec!["%reply=.sha256"]);
+        assert_eq!(
+            view.get_forks_for_root(root).unwrap(),
+            vec![(root.to_string(), "%reply=.sha256".to_string())]
+        );
+        assert_eq!(view.get_replies_to(root).unwrap(), vec!["%reply=.sha256"]);
+        assert_eq!(view.get_mentions_of(OTHER_AUTHOR).unwrap(), vec!["%reply=.sha256"]);
+        assert_eq!(
+            view.get_blobs_referenced_by_message("%reply=.sha256").unwrap(),
+            vec![blob]
+        );
+
+        let count = |sql: &str| -> i64 {
+            view.connection
+                .query_row(sql, NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM keys WHERE key LIKE 'ssb:%'"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM authors WHERE author LIKE 'ssb:%'"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM blobs WHERE blob LIKE 'ssb:%'"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM contacts_raw"), 1);
+    }
+
+    #[test]
+    fn append() {
+        let expected_seq = 1234;
+        let mut view = make_test_view();
+        let jsn = FIXTURE_POST;
+        view.append(expected_seq, jsn.as_bytes());
+        let seq = view
+            .get_seq_by_key("%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256")
+            .unwrap();
+        assert_eq!(seq, expected_seq as i64);
+
+        let seqs = view.get_seqs_by_type("post").unwrap();
+        assert_eq!(seqs[0], expected_seq as i64);
+    }
+
+    #[test]
+    fn seqs_by_keys() {
+        let mut view = make_test_view();
+        assert!(view.get_seqs_by_keys(&[]).unwrap().is_empty());
+
+        let items = (1..=7)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let keys = (1..=10)
+            .map(|i| format!("%post{}=.sha256", i))
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| key.as_str()).collect::<Vec<_>>();
+
+        let seqs = view.get_seqs_by_keys(&keys).unwrap();
+        assert_eq!(seqs.len(), 7);
+        assert_eq!(seqs["%post3=.sha256"], 3);
+        assert!(!seqs.contains_key("%post8=.sha256"));
+    }
+
+    #[test]
+    fn message_by_key() {
+        let mut view = make_test_view();
+        view.append(1, FIXTURE_POST.as_bytes());
+
+        let expected = SsbMessage::from_bytes(FIXTURE_POST.as_bytes()).unwrap();
+        let message = view.get_message_by_key(&expected.key).unwrap().unwrap();
+
+        assert_eq!(message.key, expected.key);
+        assert_eq!(message.value.author, expected.value.author);
+        assert_eq!(message.value.sequence, expected.value.sequence);
+        assert_eq!(message.value.timestamp, expected.value.timestamp);
+        assert_eq!(message.value.content, expected.value.content);
+        assert!(view.get_message_by_key("%unknown=.sha256").unwrap().is_none());
+    }
+
+    #[test]
+    fn ssb_message_from_bytes_round_trips() {
+        let message = SsbMessage::from_bytes(FIXTURE_POST.as_bytes()).unwrap();
+        assert_eq!(
+            message.key,
+            "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256"
+        );
+        assert_eq!(message.value.author, AUTHOR);
+        assert_eq!(message.value.sequence, 4797);
+
+        let bytes = to_vec(&message).unwrap();
+        let round_tripped = SsbMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            to_value(&round_tripped).unwrap(),
+            to_value(&message).unwrap()
+        );
+    }
+
+    #[test]
+    fn messages_by_author_are_in_sequence_order() {
+        let mut view = make_test_view();
+        let second = message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post"}));
+        let first = message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"}));
+        view.append_batch(&[(1, second), (2, first)]).unwrap();
+
+        let keys = view.get_messages_by_author(AUTHOR, None).unwrap();
+        assert_eq!(keys, vec!["%first=.sha256", "%second=.sha256"]);
+    }
+
+    #[test]
+    fn attached_databases_can_be_queried() {
+        let db = TempDb::new("attached_databases_can_be_queried");
+        let filename = db.path();
+        {
+            let mut other = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+            other
+                .append_with_result(1, FIXTURE_CONTACT.as_bytes())
+                .unwrap();
+        }
+
+        let mut view = make_test_view();
+        view.append_with_result(1, FIXTURE_POST.as_bytes()).unwrap();
+        view.attach_database(filename, "other").unwrap();
+
+        assert_eq!(
+            view.get_messages_by_author(AUTHOR, None).unwrap(),
+            vec!["%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256"]
+        );
+        assert_eq!(
+            view.get_messages_by_author(OTHER_AUTHOR, Some("other"))
+                .unwrap(),
+            vec!["%IXFZNn/nqMo0K25MAsuik2uM8Ji1MYIXZpsTkbfJZTw=.sha256"]
+        );
+        assert!(view
+            .get_messages_by_author(OTHER_AUTHOR, None)
+            .unwrap()
+            .is_empty());
+        assert!(view
+            .get_messages_by_author(AUTHOR, Some("main; DROP TABLE keys"))
+            .is_err());
+
+        view.detach_database("other").unwrap();
+        assert!(view
+            .get_messages_by_author(OTHER_AUTHOR, Some("other"))
+            .is_err());
+    }
+
+    #[test]
+    fn seqs_by_type_paged() {
+        let mut view = make_test_view();
+        let items = (1..=200)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let page_1 = view.get_seqs_by_type_paged("post", 100, 0).unwrap();
+        let page_2 = view.get_seqs_by_type_paged("post", 100, 100).unwrap();
+
+        assert_eq!(page_1.len(), 100);
+        assert_eq!(page_2.len(), 100);
+        assert!(page_1.iter().all(|seq| !page_2.contains(seq)));
+
+        let mut all = page_1.clone();
+        all.extend(page_2);
+        assert_eq!(all, view.get_seqs_by_type("post").unwrap());
+    }
+
+    #[test]
+    fn thread_by_root() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (2, message_bytes("%reply1=.sha256", AUTHOR, 2, json!({"type": "post", "root": root}))),
+            (3, message_bytes("%reply2=.sha256", AUTHOR, 3, json!({"type": "post", "root": root}))),
+            (4, message_bytes("%unrelated=.sha256", AUTHOR, 4, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let thread = view.get_thread_by_root(root).unwrap();
+        assert_eq!(thread, vec![root, "%reply1=.sha256", "%reply2=.sha256"]);
+    }
+
+    #[test]
+    fn thread_participant_count() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let reply = |key: &str, author: &str, sequence: u32| {
+            message_bytes(key, author, sequence, json!({"type": "post", "root": root}))
+        };
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (2, reply("%b1=.sha256", "@b=.ed25519", 1)),
+            (3, reply("%c1=.sha256", "@c=.ed25519", 1)),
+            (4, reply("%b2=.sha256", "@b=.ed25519", 2)),
+            (5, reply("%a2=.sha256", AUTHOR, 2)),
+            (6, message_bytes("%elsewhere=.sha256", "@d=.ed25519", 1, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_thread_participant_count(root).unwrap(), 3);
+        assert_eq!(view.get_thread_participant_count("%unknown=.sha256").unwrap(), 0);
+    }
+
+    #[test]
+    fn replies_to_branch() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%reply1=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "root": root, "branch": root}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%reply2=.sha256",
+                    AUTHOR,
+                    3,
+                    json!({"type": "post", "root": root, "branch": [root, "%other=.sha256"]}),
+                ),
+            ),
+            (
+                4,
+                message_bytes(
+                    "%nested=.sha256",
+                    AUTHOR,
+                    4,
+                    json!({"type": "post", "root": root, "branch": "%reply1=.sha256"}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let replies = view.get_replies_to(root).unwrap();
+        assert_eq!(replies, vec!["%reply1=.sha256", "%reply2=.sha256"]);
+    }
+
+    #[test]
+    fn root_messages() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, message_bytes("%root1=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (2, message_bytes("%root2=.sha256", AUTHOR, 2, json!({"type": "post"}))),
+            (3, message_bytes("%reply1=.sha256", AUTHOR, 3, json!({"type": "post", "root": "%root1=.sha256"}))),
+            (4, message_bytes("%root3=.sha256", AUTHOR, 4, json!({"type": "post"}))),
+            (5, message_bytes("%reply2=.sha256", AUTHOR, 5, json!({"type": "post", "root": "%root2=.sha256"}))),
+            (6, message_bytes("%vote=.sha256", AUTHOR, 6, json!({"type": "vote"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let roots = view.get_root_messages("post", 10, 0).unwrap();
+        assert_eq!(roots, vec!["%root3=.sha256", "%root2=.sha256", "%root1=.sha256"]);
+        assert_eq!(view.get_root_messages("post", 1, 1).unwrap(), vec!["%root2=.sha256"]);
+    }
+
+    #[test]
+    fn wal_mode_allows_reading_while_appending() {
+        let db = TempDb::new("wal_mode_allows_reading_while_appending");
+        let filename = db.path();
+        let keys = Vec::new();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        view.append(
+            1,
+            &message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"})),
+        );
+
+        let reader = Connection::open(filename).unwrap();
+        let mut stmt = reader.prepare("SELECT key FROM messages").unwrap();
+        let mut rows = stmt.query(NO_PARAMS).unwrap();
+        assert!(rows.next().is_some());
+
+        view.append(
+            2,
+            &message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post"})),
+        );
+        assert_eq!(view.get_latest().unwrap(), 2);
+    }
+
+    #[test]
+    fn latest_sequence_for_author() {
+        let mut view = make_test_view();
+        assert_eq!(view.get_latest_sequence_for_author(AUTHOR).unwrap(), None);
+
+        let items = (1..=3)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_latest_sequence_for_author(AUTHOR).unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn append_batch_skips_failed_items() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (2, message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post"}))),
+            (3, b"{\"key\": \"%broken".to_vec()),
+            (4, message_bytes("%fourth=.sha256", AUTHOR, 3, json!({"type": "post"}))),
+            (5, message_bytes("%fifth=.sha256", AUTHOR, 4, json!({"type": "post"}))),
+        ];
+
+        let results = view.append_batch(&items).unwrap();
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0], AppendResult::Ok(1));
+        assert_eq!(results[1], AppendResult::Ok(2));
+        match &results[2] {
+            AppendResult::Skipped(3, _) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(results[3], AppendResult::Ok(4));
+        assert_eq!(results[4], AppendResult::Ok(5));
+
+        assert_eq!(view.get_total_message_count().unwrap(), 4);
+        for key in &[
+            "%first=.sha256",
+            "%second=.sha256",
+            "%fourth=.sha256",
+            "%fifth=.sha256",
+        ] {
+            assert!(view.get_seq_by_key(key).is_ok());
+        }
+    }
+
+    #[test]
+    fn append_batch_in_chunks() {
+        let mut view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .batch_chunk_size(100)
+            .build()
+            .unwrap();
+        let posts = |range: std::ops::RangeInclusive<u64>| {
+            range
+                .map(|i| {
+                    let key = format!("%post{}=.sha256", i);
+                    (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        view.append_batch(&posts(1..=1000)).unwrap();
+        assert_eq!(view.get_total_message_count().unwrap(), 1000);
+
+        //Only the broken item is left out, whichever chunk it is in.
+        let mut items = posts(1001..=1150);
+        items.push((1151, b"{\"key\": \"%broken".to_vec()));
+
+        let results = view.append_batch(&items).unwrap();
+        assert_eq!(results.len(), 151);
+        match results.last() {
+            Some(AppendResult::Skipped(1151, _)) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(view.get_total_message_count().unwrap(), 1150);
+    }
+
+    #[test]
+    fn append_rejects_content_types_not_in_the_allowlist() {
+        let (pk, _) = gen_keypair();
+        let mut view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .content_type_allowlist(vec!["post".to_string()])
+            .build()
+            .unwrap();
+
+        let vote = message_bytes("%vote=.sha256", AUTHOR, 1, json!({"type": "vote"}));
+        let err = view.append_with_result(1, &vote).unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::DisallowedContentType { content_type } => {
+                assert_eq!(content_type, "vote")
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        let post = message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"}));
+        view.append_with_result(2, &post).unwrap();
+        let private = private_message_bytes("%private=.sha256", 2, json!({"type": "vote"}), &pk);
+        view.append_with_result(3, &private).unwrap();
+
+        assert_eq!(view.get_total_message_count().unwrap(), 2);
+        assert!(view.get_seq_by_key("%vote=.sha256").is_err());
+    }
+
+    #[test]
+    fn append_rejects_messages_over_the_size_limit() {
+        let mut view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .max_message_size(Some(1024 * 1024))
+            .build()
+            .unwrap();
+
+        let huge = vec![b' '; 2 * 1024 * 1024];
+        let err = view.append_with_result(1, &huge).unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::MessageTooLarge { size, limit } => {
+                assert_eq!(size, 2 * 1024 * 1024);
+                assert_eq!(limit, 1024 * 1024);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        assert_eq!(view.get_latest().unwrap(), 0);
+        view.check_db_integrity().unwrap();
+        view.append_with_result(1, FIXTURE_POST.as_bytes()).unwrap();
+        assert_eq!(view.get_latest().unwrap(), 1);
+    }
+
+    #[test]
+    fn builder_sets_synchronous_mode() {
+        let view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .synchronous(SynchronousMode::Full)
+            .build()
+            .unwrap();
+
+        //sqlite reports the mode by number, FULL is 2.
+        let synchronous: i64 = view
+            .connection
+            .query_row("PRAGMA synchronous", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(synchronous, 2);
+    }
+
+    #[test]
+    fn builder_sets_auto_vacuum_mode() {
+        let auto_vacuum = |view: &FlumeViewSql| -> i64 {
+            view.connection
+                .query_row("PRAGMA auto_vacuum", NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+
+        //sqlite reports the mode by number, FULL is 1 and INCREMENTAL is 2.
+        let view = FlumeViewSqlBuilder::new().in_memory().build().unwrap();
+        assert_eq!(auto_vacuum(&view), 2);
+
+        let view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .auto_vacuum(AutoVacuumMode::Full)
+            .build()
+            .unwrap();
+        assert_eq!(auto_vacuum(&view), 1);
+    }
+
+    #[test]
+    fn builder_sets_temp_store_mode() {
+        let temp_store = |view: &FlumeViewSql| -> i64 {
+            view.connection
+                .query_row("PRAGMA temp_store", NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+
+        //sqlite reports the mode by number, FILE is 1 and MEMORY is 2.
+        let view = FlumeViewSqlBuilder::new().in_memory().build().unwrap();
+        assert_eq!(temp_store(&view), 2);
+
+        let view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .temp_store(TempStoreMode::File)
+            .build()
+            .unwrap();
+        assert_eq!(temp_store(&view), 1);
+    }
+
+    #[test]
+    fn user_version_persists_across_reopen() {
+        let db = TempDb::new("user_version_persists_across_reopen");
+        let filename = db.path();
+
+        {
+            let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+            assert_eq!(
+                view.get_user_version().unwrap(),
+                CURRENT_SCHEMA_VERSION as i32
+            );
+            view.set_user_version(42).unwrap();
+        }
+
+        let view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        assert_eq!(view.get_user_version().unwrap(), 42);
+    }
+
+    #[test]
+    fn builder_sets_page_size() {
+        let db = TempDb::new("builder_sets_page_size");
+        let filename = db.path();
+
+        let view = FlumeViewSqlBuilder::new()
+            .path(filename)
+            .page_size(4096)
+            .build()
+            .unwrap();
+
+        let page_size: i64 = view
+            .connection
+            .query_row("PRAGMA page_size", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(page_size, 4096);
+    }
+
+    #[test]
+    fn from_uri_opens_a_shared_in_memory_db() {
+        let uri = "file:from_uri_test?mode=memory&cache=shared";
+        let mut first = FlumeViewSql::from_uri(uri, Vec::new()).unwrap();
+        first.append_with_result(1, FIXTURE_POST.as_bytes()).unwrap();
+
+        let mut second = FlumeViewSql::from_uri(uri, Vec::new()).unwrap();
+        assert_eq!(second.get_latest().unwrap(), 1);
+        assert_eq!(second.get_total_message_count().unwrap(), 1);
+
+        let err = FlumeViewSql::from_uri("/tmp/not_a_uri.sqlite3", Vec::new())
+            .err()
+            .unwrap();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::InvalidUri { uri } => assert_eq!(uri, "/tmp/not_a_uri.sqlite3"),
+            err => panic!("unexpected error {}", err),
+        }
+        assert!(FlumeViewSql::from_uri("file:bad?cache", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn builder_sets_mmap_size() {
+        let db = TempDb::new("builder_sets_mmap_size");
+        let filename = db.path();
+
+        let view = FlumeViewSqlBuilder::new()
+            .path(filename)
+            .mmap_size(64 * 1024 * 1024)
+            .build()
+            .unwrap();
+
+        let mmap_size: i64 = view
+            .connection
+            .query_row("PRAGMA mmap_size", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(mmap_size, 64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn message_json_round_trips() {
+        let mut view = make_test_view();
+        let content = json!({"type": "post", "text": "hello", "mentions": [{"link": "@someone"}]});
+        view.append(
+            1,
+            &message_bytes("%post=.sha256", AUTHOR, 1, content.clone()),
+        );
+
+        let stored = view.get_message_json("%post=.sha256").unwrap().unwrap();
+        assert_eq!(from_str::<Value>(&stored).unwrap(), content);
+        assert_eq!(view.get_message_json("%missing=.sha256").unwrap(), None);
+    }
+
+    #[test]
+    fn deferred_indices_are_created_by_finalize_import() {
+        let mut view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .defer_index_creation(true)
+            .build()
+            .unwrap();
+        let index_count = |view: &FlumeViewSql| -> i64 {
+            view.connection
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE type='index' AND name='content_type_index'",
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(index_count(&view), 0);
+
+        let items = (1..=10)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        view.finalize_import().unwrap();
+        assert_eq!(index_count(&view), 1);
+        assert_eq!(
+            view.get_seqs_by_type("post").unwrap(),
+            (1..=10).collect::<Vec<i64>>()
+        );
+    }
+
+    #[test]
+    fn rebuild_indices_reports_progress() {
+        use std::cell::RefCell;
+
+        let mut view = make_test_view();
+        let calls = RefCell::new(Vec::new());
+
+        view.rebuild_indices_with_progress(|progress| calls.borrow_mut().push(progress))
+            .unwrap();
+
+        let calls = calls.into_inner();
+        assert_eq!(calls.len(), 2 * INDICES.len());
+        assert_eq!(calls[0].step, 0);
+        assert_eq!(calls[1].step, 1);
+        assert_eq!(calls[0].index_name, calls[1].index_name);
+
+        let last = calls.last().unwrap();
+        assert_eq!(last.step, last.total);
+        assert_eq!(last.total as usize, INDICES.len());
+    }
+
+    #[test]
+    fn rebuild_indices_twice() {
+        let mut view = make_test_view();
+        view.append(
+            1,
+            &message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"})),
+        );
+
+        view.rebuild_indices().unwrap();
+        view.rebuild_indices().unwrap();
+
+        assert_eq!(view.get_seqs_by_author(AUTHOR).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn all_content_types() {
+        let mut view = make_test_view();
+        assert!(view.get_all_content_types().unwrap().is_empty());
+
+        let items = vec![
+            (1, message_bytes("%post1=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (2, message_bytes("%vote=.sha256", AUTHOR, 2, json!({"type": "vote"}))),
+            (3, message_bytes("%post2=.sha256", AUTHOR, 3, json!({"type": "post"}))),
+            (4, message_bytes("%contact=.sha256", AUTHOR, 4, json!({"type": "contact"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_all_content_types().unwrap(),
+            vec!["contact", "post", "vote"]
+        );
+    }
+
+    #[test]
+    fn count_by_type() {
+        let mut view = make_test_view();
+        let items = (1..=15)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                let content_type = if i <= 10 { "post" } else { "vote" };
+                let content = json!({ "type": content_type });
+                (i, message_bytes(&key, AUTHOR, i as u32, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_count_by_type("post").unwrap(), 10);
+        assert_eq!(view.get_count_by_type("vote").unwrap(), 5);
+        assert_eq!(view.get_count_by_type("about").unwrap(), 0);
+        assert_eq!(view.get_total_message_count().unwrap(), 15);
+    }
+
+    #[test]
+    fn links_from_and_to_message() {
+        let mut view = make_test_view();
+        let content = json!({
+            "type": "post",
+            "mentions": [{"link": "%target1=.sha256"}, {"link": "%target2=.sha256"}]
+        });
+        view.append(1, &message_bytes("%source=.sha256", AUTHOR, 1, content));
+
+        let mut links_from = view.get_links_from_message("%source=.sha256").unwrap();
+        links_from.sort();
+        assert_eq!(links_from, vec!["%target1=.sha256", "%target2=.sha256"]);
+
+        assert_eq!(
+            view.get_links_to_message("%target1=.sha256").unwrap(),
+            vec!["%source=.sha256"]
+        );
+        assert_eq!(
+            view.get_links_to_message("%target2=.sha256").unwrap(),
+            vec!["%source=.sha256"]
+        );
+    }
+
+    #[test]
+    fn blobs_referenced_by_message() {
+        let mut view = make_test_view();
+        let content = json!({
+            "type": "post",
+            "mentions": [{"link": "&blob=.sha256"}, {"link": "%target=.sha256"}]
+        });
+        view.append(1, &message_bytes("%source=.sha256", AUTHOR, 1, content));
+
+        assert_eq!(
+            view.get_blobs_referenced_by_message("%source=.sha256").unwrap(),
+            vec!["&blob=.sha256"]
+        );
+        assert_eq!(
+            view.get_links_from_message("%source=.sha256").unwrap(),
+            vec!["%target=.sha256"]
+        );
+    }
+
+    #[test]
+    fn thread_by_root_uses_root_index() {
+        let mut view = make_test_view();
+        let roots = (0..10)
+            .map(|i| format!("%root{}=.sha256", i))
+            .collect::<Vec<_>>();
+        let items = (0..1000)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                let content = if i < 10 {
+                    json!({"type": "post"})
+                } else {
+                    json!({"type": "post", "root": roots[i % 10]})
+                };
+                let key = if i < 10 { roots[i].clone() } else { key };
+                (i as u64 + 1, message_bytes(&key, AUTHOR, i as u32 + 1, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_thread_by_root(&roots[3]).unwrap().len(), 100);
+
+        let mut stmt = view
+            .connection
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", THREAD_BY_ROOT_QUERY))
+            .unwrap();
+        let details = stmt
+            .query_map(&[&roots[3]], |row| row.get(3))
+            .unwrap()
+            .collect::<std::result::Result<Vec<String>, _>>()
+            .unwrap();
+
+        assert!(details
+            .iter()
+            .all(|detail| !(detail.starts_with("SCAN") && detail.contains("messages_raw"))));
+    }
+
+    #[test]
+    fn messages_since_received_time() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, message_bytes("%third=.sha256", AUTHOR, 3, json!({"type": "post"}))),
+            (2, message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (3, message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_messages_since_received_time(0.0, 10).unwrap(),
+            vec!["%first=.sha256", "%second=.sha256", "%third=.sha256"]
+        );
+        assert_eq!(
+            view.get_messages_since_received_time(1543959001934.0, 1)
+                .unwrap(),
+            vec!["%second=.sha256"]
+        );
+    }
+
+    #[test]
+    fn authors_list() {
+        let mut view = make_test_view();
+        let alice = "@alice=.ed25519";
+        let bob = "@bob=.ed25519";
+        let carol = "@carol=.ed25519";
+        let items = vec![
+            (1, message_bytes("%a1=.sha256", alice, 1, json!({"type": "post"}))),
+            (2, message_bytes("%c1=.sha256", carol, 1, json!({"type": "post"}))),
+            (3, message_bytes("%b1=.sha256", bob, 1, json!({"type": "post"}))),
+            (4, message_bytes("%a2=.sha256", alice, 2, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_authors_list().unwrap(), vec![alice, bob, carol]);
+    }
+
+    #[test]
+    fn current_heads_of_linear_thread() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%reply1=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "root": root, "branch": root}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%reply2=.sha256",
+                    AUTHOR,
+                    3,
+                    json!({"type": "post", "root": root, "branch": "%reply1=.sha256"}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_current_heads(root).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn decrypted_and_failed_messages() {
+        let (my_public_key, my_secret_key) = gen_keypair();
+        let (their_public_key, _) = gen_keypair();
+
+        let mut view = FlumeViewSql::new_in_memory(vec![my_secret_key]).unwrap();
+        let items = vec![
+            (1, message_bytes("%public=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                private_message_bytes("%mine=.sha256", 2, json!({"type": "post"}), &my_public_key),
+            ),
+            (
+                3,
+                private_message_bytes(
+                    "%theirs=.sha256",
+                    3,
+                    json!({"type": "post"}),
+                    &their_public_key,
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_decrypted_messages(10, 0).unwrap(),
+            vec!["%mine=.sha256"]
+        );
+        assert_eq!(
+            view.get_encrypted_failed_messages(10, 0).unwrap(),
+            vec!["%theirs=.sha256"]
+        );
+        assert_eq!(view.get_message_json("%theirs=.sha256").unwrap(), None);
+    }
+
+    #[test]
+    fn message_count_per_author() {
+        let mut view = make_test_view();
+        let alice = "@alice=.ed25519";
+        let bob = "@bob=.ed25519";
+        let items = (1..=10)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                let (author, sequence) = if i <= 3 { (alice, i) } else { (bob, i - 3) };
+                let content = json!({"type": "post"});
+                (i, message_bytes(&key, author, sequence as u32, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let counts = view.get_message_count_per_author().unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[alice], 3);
+        assert_eq!(counts[bob], 7);
+    }
+
+    #[test]
+    fn opening_old_schema_runs_migrations() {
+        let db = TempDb::new("opening_old_schema_runs_migrations");
+        let filename = db.path();
+
+        {
+            let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+            view.append(
+                1,
+                &message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"})),
+            );
+
+            //Pretend this db was made before the received_time index existed.
+            view.connection
+                .execute("DROP INDEX received_time_index", NO_PARAMS)
+                .unwrap();
+            set_schema_version(&view.connection, 0).unwrap();
+        }
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+
+        assert_eq!(
+            get_schema_version(&view.connection).unwrap(),
+            CURRENT_SCHEMA_VERSION
+        );
+        let index_count: i64 = view
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='index' AND name='received_time_index'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(index_count, 1);
+        assert_eq!(view.get_seq_by_key("%post=.sha256").unwrap(), 1);
+    }
+
+    #[test]
+    fn migrating_reindexes_mentions() {
+        let db = TempDb::new("migrating_reindexes_mentions");
+        let filename = db.path();
+        let content = json!({"type": "post", "mentions": [{"link": OTHER_AUTHOR}]});
+
+        {
+            let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+            view.append_with_result(1, &message_bytes("%post=.sha256", AUTHOR, 1, content))
+                .unwrap();
+
+            //Schema 7 stored the mentioned ref's key id where the author id belongs.
+            view.connection
+                .execute("DELETE FROM mentions_raw", NO_PARAMS)
+                .unwrap();
+            let key_id = find_or_create_key(&view.connection, OTHER_AUTHOR).unwrap();
+            let message_key_id = find_or_create_key(&view.connection, "%post=.sha256").unwrap();
+            view.connection
+                .execute(
+                    "INSERT INTO mentions_raw (link_from_key_id, link_to_author_id) VALUES (?, ?)",
+                    &[&message_key_id, &key_id],
+                )
+                .unwrap();
+            set_schema_version(&view.connection, 7).unwrap();
+        }
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+
+        assert_eq!(
+            get_schema_version(&view.connection).unwrap(),
+            CURRENT_SCHEMA_VERSION
+        );
+        assert_eq!(view.get_seq_by_key("%post=.sha256").unwrap(), 1);
+        assert_eq!(view.get_mentions_of(OTHER_AUTHOR).unwrap(), vec!["%post=.sha256"]);
+    }
+
+    #[test]
+    fn clone_to_path() {
+        let db = TempDb::new("clone_to_path_source");
+        let clone_db = TempDb::new("clone_to_path_dest");
+        let filename = db.path();
+        let clone_filename = clone_db.path();
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        let items = (1..=100)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        view.clone_to_path(clone_filename, None).unwrap();
+
+        let clone = FlumeViewSql::open_existing(clone_filename, Vec::new()).unwrap();
+        assert_eq!(clone.get_latest().unwrap(), view.get_latest().unwrap());
+    }
+
+    #[test]
+    fn vacuum_into_writes_a_compacted_copy() {
+        let db = TempDb::new("vacuum_into_source");
+        let copy_db = TempDb::new("vacuum_into_copy");
+
+        let mut view = FlumeViewSql::new(db.path(), Vec::new(), "").unwrap();
+        let items = (1..=100)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+        view.truncate_after(50).unwrap();
+
+        view.vacuum_into(copy_db.path()).unwrap();
+
+        let mut copy = FlumeViewSql::open_existing(copy_db.path(), Vec::new()).unwrap();
+        assert_eq!(copy.get_latest().unwrap(), 50);
+        assert_eq!(copy.get_seq_by_key("%post50=.sha256").unwrap(), 50);
+        copy.check_db_integrity().unwrap();
+
+        let free_pages: i64 = copy
+            .connection
+            .query_row("PRAGMA freelist_count", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(free_pages, 0);
+        assert_eq!(view.get_latest().unwrap(), 50);
+    }
+
+    #[test]
+    fn wal_checkpoint_truncate_empties_the_wal() {
+        let db = TempDb::new("wal_checkpoint_truncate_empties_the_wal");
+        let filename = db.path();
+        let wal_filename = format!("{}-wal", filename);
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        let items = (1..=100)
+            .map(|i| {
+                let key = format!("%post{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let wal_size = std::fs::metadata(&wal_filename).unwrap().len();
+        assert!(wal_size > 0);
+
+        let (wal_frames, checkpointed_frames) =
+            view.wal_checkpoint(WalCheckpointMode::Truncate).unwrap();
+        assert_eq!(wal_frames, checkpointed_frames);
+        assert!(std::fs::metadata(&wal_filename).unwrap().len() < wal_size);
+    }
+
+    #[test]
+    fn export_to_ndjson() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%second=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "root": "%first=.sha256"}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let mut exported = Vec::new();
+        assert_eq!(view.export_to_ndjson(&mut exported).unwrap(), 2);
+
+        let lines = String::from_utf8(exported).unwrap();
+        let lines = lines
+            .lines()
+            .map(|line| from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["key"], "%first=.sha256");
+        assert_eq!(lines[0]["content"]["type"], "post");
+        assert_eq!(lines[1]["key"], "%second=.sha256");
+        assert_eq!(lines[1]["author"], AUTHOR);
+        assert_eq!(lines[1]["root"], "%first=.sha256");
+    }
+
+    #[test]
+    fn truncate_after_allows_replay() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let reply = message_bytes(
+            "%reply2=.sha256",
+            AUTHOR,
+            3,
+            json!({"type": "post", "root": root, "branch": "%reply1=.sha256"}),
+        );
+        let follow = message_bytes(
+            "%follow=.sha256",
+            AUTHOR,
+            4,
+            json!({"type": "contact", "contact": OTHER_AUTHOR, "following": true}),
+        );
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%reply1=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "root": root, "branch": root}),
+                ),
+            ),
+            (3, reply.clone()),
+            (4, follow.clone()),
+        ];
+        view.append_batch(&items).unwrap();
+        assert_eq!(view.get_current_heads(root).unwrap(), vec![3]);
+
+        let contact_rows = |view: &FlumeViewSql| -> i64 {
+            view.connection
+                .query_row(
+                    "SELECT COUNT(*) FROM contacts_raw
+                    JOIN keys ON keys.id = contacts_raw.link_from_key_id
+                    WHERE keys.key = '%follow=.sha256'",
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(contact_rows(&view), 1);
+
+        assert_eq!(view.truncate_after(2).unwrap(), 2);
+
+        assert_eq!(view.get_latest().unwrap(), 2);
+        assert_eq!(view.get_current_heads(root).unwrap(), vec![2]);
+        assert!(view.get_replies_to("%reply1=.sha256").unwrap().is_empty());
+        assert_eq!(contact_rows(&view), 0);
+
+        view.append_with_result(3, &reply).unwrap();
+        view.append_with_result(4, &follow).unwrap();
+        assert_eq!(view.get_latest().unwrap(), 4);
+        assert_eq!(view.get_current_heads(root).unwrap(), vec![3]);
+        assert_eq!(contact_rows(&view), 1);
+    }
+
+    #[test]
+    fn key_filter_tracks_stored_keys() {
+        let db = TempDb::new("key_filter_tracks_stored_keys");
+        let filename = db.path();
+
+        let open = || {
+            FlumeViewSqlBuilder::new()
+                .path(filename)
+                .key_filter(true)
+                .build()
+                .unwrap()
+        };
+
+        {
+            let mut view = open();
+            assert!(!view.key_probably_exists("%post=.sha256"));
+
+            view.append_with_result(
+                1,
+                &message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"})),
+            )
+            .unwrap();
+            assert!(view.key_probably_exists("%post=.sha256"));
+        }
+
+        //A reopened view loads the keys already in the db.
+        let mut view = open();
+        assert!(view.key_probably_exists("%post=.sha256"));
+
+        let false_positives = (0..1000)
+            .filter(|i| view.key_probably_exists(&format!("%missing{}=.sha256", i)))
+            .count();
+        assert!(false_positives < 50);
+
+        //Keys truncated away are dropped from the filter.
+        let items = (2..=101)
+            .map(|i| {
+                let key = format!("%truncated{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+        view.truncate_after(1).unwrap();
+
+        assert!(view.key_probably_exists("%post=.sha256"));
+        let still_remembered = (2..=101)
+            .filter(|i| view.key_probably_exists(&format!("%truncated{}=.sha256", i)))
+            .count();
+        assert!(still_remembered < 10);
+    }
+
+    #[test]
+    fn key_filter_is_off_by_default() {
+        let mut view = make_test_view();
+        assert!(view.key_probably_exists("%missing=.sha256"));
+
+        view.reload_key_filter().unwrap();
+        assert!(view.key_probably_exists("%missing=.sha256"));
+    }
+
+    #[test]
+    fn drop_all_tables_resets_the_db() {
+        let mut view = make_test_view();
+        view.append_with_result(1, FIXTURE_POST.as_bytes()).unwrap();
+        view.append_with_result(2, FIXTURE_CONTACT.as_bytes()).unwrap();
+
+        view.drop_all_tables().unwrap();
+
+        assert_eq!(view.get_latest().unwrap(), 0);
+        assert!(view.get_authors_list().unwrap().is_empty());
+        assert!(view.get_links_to_message(AUTHOR).unwrap().is_empty());
+
+        view.append_with_result(1, FIXTURE_POST.as_bytes()).unwrap();
+        assert_eq!(view.get_total_message_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn ext_trait_works_through_a_box() {
+        fn count_posts<V: FlumeViewSqlExt>(view: &mut V) -> i64 {
+            view.get_count_by_type("post").unwrap()
+        }
+
+        let mut view = Box::new(make_test_view());
+        view.append_with_result(1, FIXTURE_POST.as_bytes()).unwrap();
+
+        assert_eq!(count_posts(&mut view), 1);
+        assert_eq!(FlumeViewSqlExt::get_latest(&view).unwrap(), 1);
+        assert_eq!(count_posts(&mut *view), 1);
+    }
+
+    #[test]
+    fn vacuum() {
+        let mut view = make_test_view();
+        view.append(
+            1,
+            &message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"})),
+        );
+
+        view.vacuum().unwrap();
+        assert_eq!(view.get_seq_by_key("%post=.sha256").unwrap(), 1);
+    }
+
+    #[test]
+    fn builder_sets_cache_size() {
+        let view = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .cache_size(16 * 1024)
+            .build()
+            .unwrap();
+
+        let cache_size: i64 = view
+            .connection
+            .query_row("PRAGMA cache_size", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(cache_size, -16 * 1024);
+    }
+
+    #[test]
+    fn builder_rejects_bad_pragma_values() {
+        let err = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .journal_mode("WAL; DROP TABLE keys")
+            .build()
+            .err()
+            .unwrap();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::InvalidJournalMode { mode } => {
+                assert_eq!(mode, "WAL; DROP TABLE keys")
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        let err = FlumeViewSqlBuilder::new()
+            .in_memory()
+            .cache_size(0)
+            .build()
+            .err()
+            .unwrap();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::InvalidCacheSize {} => (),
+            err => panic!("unexpected error {}", err),
+        }
+
+        assert!(FlumeViewSqlBuilder::new()
+            .in_memory()
+            .journal_mode("memory")
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn messages_in_flume_seq_range() {
+        let mut view = make_test_view();
+        let items = (1..=50)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let messages = view.get_messages_in_flume_seq_range(10, 20).unwrap();
+        assert_eq!(messages.len(), 11);
+        assert_eq!(messages[0], (10, "%msg10=.sha256".to_string()));
+        assert_eq!(messages[10], (20, "%msg20=.sha256".to_string()));
+    }
+
+    #[test]
+    fn add_key_decrypts_stored_messages() {
+        let (public_key, secret_key) = gen_keypair();
+
+        let mut view = make_test_view();
+        let content = json!({"type": "post", "text": "read me later"});
+        view.append(
+            1,
+            &private_message_bytes("%private=.sha256", 1, content.clone(), &public_key),
+        );
+        assert_eq!(view.get_message_json("%private=.sha256").unwrap(), None);
+
+        assert_eq!(view.add_key(secret_key).unwrap(), 1);
+
+        let stored = view.get_message_json("%private=.sha256").unwrap().unwrap();
+        assert_eq!(from_str::<Value>(&stored).unwrap(), content);
+        assert_eq!(
+            view.get_decrypted_messages(10, 0).unwrap(),
+            vec!["%private=.sha256"]
+        );
+        assert_eq!(view.get_seq_by_key("%private=.sha256").unwrap(), 1);
+    }
+
+    #[test]
+    fn private_message_keys_include_undecrypted() {
+        let (my_public_key, my_secret_key) = gen_keypair();
+        let (their_public_key, _) = gen_keypair();
+
+        let mut view = FlumeViewSql::new_in_memory(vec![my_secret_key]).unwrap();
+        let mine = private_message_bytes("%mine=.sha256", 1, json!({"type": "post"}), &my_public_key);
+        let theirs =
+            private_message_bytes("%theirs=.sha256", 2, json!({"type": "post"}), &their_public_key);
+        let items = vec![
+            (1, mine),
+            (2, theirs),
+            (3, message_bytes("%public=.sha256", AUTHOR, 3, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_private_message_keys(10, 0).unwrap(),
+            vec![
+                ("%theirs=.sha256".to_string(), false),
+                ("%mine=.sha256".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            view.get_private_message_keys(1, 1).unwrap(),
+            vec![("%mine=.sha256".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn previous_key_follows_the_feed() {
+        let mut view = make_test_view();
+        view.append_with_result(1, FIXTURE_CONTACT.as_bytes()).unwrap();
+        view.append_with_result(2, FIXTURE_POST.as_bytes()).unwrap();
+
+        assert_eq!(
+            view.get_previous_key("%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256")
+                .unwrap(),
+            Some("%xsMQA2GrsZew0GSxmDSBaoxDafVaUJ07YVaDGcp65a4=.sha256".to_string())
+        );
+        assert_eq!(
+            view.get_previous_key("%IXFZNn/nqMo0K25MAsuik2uM8Ji1MYIXZpsTkbfJZTw=.sha256")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn raw_content_keeps_ciphertext() {
+        let (my_public_key, my_secret_key) = gen_keypair();
+        let (their_public_key, _) = gen_keypair();
+
+        let mut view = FlumeViewSql::new_in_memory(vec![my_secret_key]).unwrap();
+        let mine = private_message_bytes("%mine=.sha256", 1, json!({"type": "post"}), &my_public_key);
+        let theirs =
+            private_message_bytes("%theirs=.sha256", 2, json!({"type": "post"}), &their_public_key);
+        let items = vec![
+            (1, mine.clone()),
+            (2, theirs.clone()),
+            (3, message_bytes("%public=.sha256", AUTHOR, 3, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let boxed_content = |bytes: &[u8]| {
+            let message: SsbMessage = from_slice(bytes).unwrap();
+            message.value.content.as_str().map(|content| content.to_string())
+        };
+
+        assert_eq!(view.get_raw_content("%mine=.sha256").unwrap(), boxed_content(&mine));
+        assert_eq!(view.get_raw_content("%theirs=.sha256").unwrap(), boxed_content(&theirs));
+        assert_eq!(view.get_raw_content("%public=.sha256").unwrap(), None);
+    }
+
+    #[test]
+    fn seqs_by_type_and_author() {
+        let mut view = make_test_view();
+        let alice = "@alice=.ed25519";
+        let bob = "@bob=.ed25519";
+        let items = vec![
+            (1, message_bytes("%alice_post=.sha256", alice, 1, json!({"type": "post"}))),
+            (2, message_bytes("%alice_vote=.sha256", alice, 2, json!({"type": "vote"}))),
+            (3, message_bytes("%bob_post=.sha256", bob, 1, json!({"type": "post"}))),
+            (4, message_bytes("%bob_vote=.sha256", bob, 2, json!({"type": "vote"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_seqs_by_type_and_author("post", alice).unwrap(), vec![1]);
+        assert_eq!(view.get_seqs_by_type_and_author("vote", alice).unwrap(), vec![2]);
+        assert_eq!(view.get_seqs_by_type_and_author("post", bob).unwrap(), vec![3]);
+        assert_eq!(view.get_seqs_by_type_and_author("vote", bob).unwrap(), vec![4]);
+    }
+
+    #[test]
+    fn read_only_view_rejects_writes() {
+        let db = TempDb::new("read_only_view_rejects_writes");
+        let filename = db.path();
+
+        {
+            let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+            view.append(
+                1,
+                &message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"})),
+            );
+        }
+        let before = std::fs::read(filename).unwrap();
+
+        let mut view = FlumeViewSql::open_read_only(filename, Vec::new()).unwrap();
+        assert_eq!(view.get_seq_by_key("%post=.sha256").unwrap(), 1);
+
+        let items = vec![(
+            2,
+            message_bytes("%another=.sha256", AUTHOR, 2, json!({"type": "post"})),
+        )];
+        let err = view.append_batch(&items).unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::ReadOnly {} => (),
+            err => panic!("unexpected error {}", err),
+        }
+
+        view.append(2, &items[0].1);
+        assert_eq!(view.get_latest().unwrap(), 1);
+
+        drop(view);
+        assert_eq!(std::fs::read(filename).unwrap(), before);
+    }
+
+    #[test]
+    fn feed_for_author() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post", "text": "2"}))),
+            (2, message_bytes("%other=.sha256", "@other=.ed25519", 1, json!({"type": "post"}))),
+            (3, message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post", "text": "1"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let feed = view.get_feed_for_author(AUTHOR).unwrap();
+        assert_eq!(feed.len(), 2);
+        assert_eq!(feed[0].key, "%first=.sha256");
+        assert_eq!(feed[0].value.sequence, 1);
+        assert_eq!(feed[0].value.content["text"], "1");
+        assert_eq!(feed[1].key, "%second=.sha256");
+        assert_eq!(feed[1].value.sequence, 2);
+        assert!(feed.iter().all(|message| message.value.author == AUTHOR));
+    }
+
+    #[test]
+    fn mentions_of_author() {
+        let mut view = make_test_view();
+        let alice = "@alice=.ed25519";
+        let bob = "@bob=.ed25519";
+        let content = json!({
+            "type": "post",
+            "text": "hi [@alice] and [@bob]",
+            "mentions": [{"link": alice, "name": "alice"}, {"link": bob, "name": "bob"}],
+            "recps": [{"link": "@carol=.ed25519"}]
+        });
+        view.append(1, &message_bytes("%post=.sha256", AUTHOR, 1, content));
+
+        assert_eq!(view.get_mentions_of(alice).unwrap(), vec!["%post=.sha256"]);
+        assert_eq!(view.get_mentions_of(bob).unwrap(), vec!["%post=.sha256"]);
+        assert!(view.get_mentions_of("@carol=.ed25519").unwrap().is_empty());
+    }
+
+    #[test]
+    fn messages_by_channel() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, message_bytes("%ssb1=.sha256", AUTHOR, 1, json!({"type": "post", "channel": "ssb"}))),
+            (2, message_bytes("%rust=.sha256", AUTHOR, 2, json!({"type": "post", "channel": "rust"}))),
+            (3, message_bytes("%none=.sha256", AUTHOR, 3, json!({"type": "post", "channel": null}))),
+            (4, message_bytes("%ssb2=.sha256", AUTHOR, 4, json!({"type": "post", "channel": "ssb"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_messages_by_channel("ssb").unwrap(),
+            vec!["%ssb1=.sha256", "%ssb2=.sha256"]
+        );
+        assert_eq!(
+            view.get_messages_by_channel("rust").unwrap(),
+            vec!["%rust=.sha256"]
+        );
+        assert_eq!(view.get_all_channels().unwrap(), vec!["rust", "ssb"]);
+    }
+
+    #[cfg(feature = "fts")]
+    #[test]
+    fn search_messages() {
+        let mut view = make_test_view();
+        let texts = [
+            "sailing to the island",
+            "nothing to see here",
+            "more sailing photos",
+            "just a vote",
+            "hello world",
+        ];
+        let items = texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let key = format!("%msg{}=.sha256", i);
+                let content = json!({"type": "post", "text": text});
+                (i as u64 + 1, message_bytes(&key, AUTHOR, i as u32 + 1, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let mut keys = view.search_messages("sailing").unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["%msg0=.sha256", "%msg2=.sha256"]);
+    }
 
     #[test]
-    fn find_values_in_object() {
-        let obj = json!({ "key": 1, "value": {"link": "hello", "array": [{"link": "piet"}], "deeper": {"link": "world"}}});
+    fn contacts_for_author_keeps_latest_state() {
+        let mut view = make_test_view();
+        let bob = "@bob=.ed25519";
+        let carol = "@carol=.ed25519";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%follow=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({"type": "contact", "contact": bob, "following": true}),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%block=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "contact", "contact": carol, "blocking": true}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%unfollow=.sha256",
+                    AUTHOR,
+                    3,
+                    json!({"type": "contact", "contact": bob, "following": false}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
 
-        let mut vec = Vec::new();
-        find_values_in_object_by_key(&obj, "link", &mut vec);
+        assert_eq!(
+            view.get_contacts_for_author(AUTHOR).unwrap(),
+            vec![(bob.to_string(), false, false), (carol.to_string(), false, true)]
+        );
+    }
 
-        assert_eq!(vec.len(), 3);
-        assert_eq!(vec[0].as_str().unwrap(), "hello");
-        assert_eq!(vec[1].as_str().unwrap(), "piet");
-        assert_eq!(vec[2].as_str().unwrap(), "world");
+    #[test]
+    fn follows_and_followers_reflect_unfollows() {
+        let mut view = make_test_view();
+        let bob = "@bob=.ed25519";
+        let carol = "@carol=.ed25519";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%followBob=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({"type": "contact", "contact": bob, "following": true}),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%followCarol=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "contact", "contact": carol, "following": true}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%carolFollowsBob=.sha256",
+                    carol,
+                    1,
+                    json!({"type": "contact", "contact": bob, "following": true}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_follows_for_author(AUTHOR).unwrap(),
+            vec![bob.to_string(), carol.to_string()]
+        );
+        assert_eq!(
+            view.get_followers_for_author(bob).unwrap(),
+            vec![AUTHOR.to_string(), carol.to_string()]
+        );
+
+        view.append_with_result(
+            4,
+            &message_bytes(
+                "%unfollowBob=.sha256",
+                AUTHOR,
+                3,
+                json!({"type": "contact", "contact": bob, "following": false}),
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            view.get_follows_for_author(AUTHOR).unwrap(),
+            vec![carol.to_string()]
+        );
+        assert_eq!(
+            view.get_followers_for_author(bob).unwrap(),
+            vec![carol.to_string()]
+        );
+        assert!(view.get_followers_for_author(AUTHOR).unwrap().is_empty());
     }
 
     #[test]
-    fn open_connection() {
-        let filename = "/tmp/test123456.sqlite3";
-        let keys = Vec::new();
-        std::fs::remove_file(filename.clone())
-            .or::<Result<()>>(Ok(()))
+    fn social_graph_keeps_latest_edges() {
+        let mut view = make_test_view();
+        let bob = "@bob=.ed25519";
+        let carol = "@carol=.ed25519";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%follow=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({"type": "contact", "contact": bob, "following": true}),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%bobFollows=.sha256",
+                    bob,
+                    1,
+                    json!({"type": "contact", "contact": carol, "following": true}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%unfollow=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "contact", "contact": bob, "following": false}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let graph = view.get_social_graph().unwrap();
+        assert_eq!(graph.len(), 2);
+        assert_eq!(graph[AUTHOR], vec![(bob.to_string(), false)]);
+        assert_eq!(graph[bob], vec![(carol.to_string(), true)]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn append_batch_async() {
+        use std::sync::{Arc, Mutex};
+
+        let view = Arc::new(Mutex::new(make_test_view()));
+        let items = vec![
+            (
+                1,
+                message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"})),
+            ),
+            (
+                2,
+                message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post"})),
+            ),
+        ];
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
             .unwrap();
-        FlumeViewSql::new(filename, keys, "").unwrap();
-        assert!(true)
+        let results = runtime
+            .block_on(FlumeViewSql::append_batch_async(view.clone(), items))
+            .unwrap();
+
+        assert_eq!(results, vec![AppendResult::Ok(1), AppendResult::Ok(2)]);
+        assert_eq!(view.lock().unwrap().get_latest().unwrap(), 2);
+
+        //A panic while the view is locked poisons the mutex, which is an error rather than a panic.
+        let poisoner = view.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison the view's mutex");
+        })
+        .join();
+
+        let err = runtime
+            .block_on(FlumeViewSql::append_batch_async(view, Vec::new()))
+            .unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::ViewLockPoisoned {} => (),
+            err => panic!("unexpected error {}", err),
+        }
     }
 
     #[test]
-    fn append() {
-        let expected_seq = 1234;
-        let filename = "/tmp/test12345.sqlite3";
-        let keys = Vec::new();
-        std::fs::remove_file(filename.clone())
-            .or::<Result<()>>(Ok(()))
+    fn sequence_gaps_for_author() {
+        let mut view = make_test_view();
+        let items = [1, 2, 5, 6, 10]
+            .iter()
+            .enumerate()
+            .map(|(i, sequence)| {
+                let key = format!("%msg{}=.sha256", sequence);
+                let content = json!({"type": "post"});
+                (i as u64 + 1, message_bytes(&key, AUTHOR, *sequence, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let gaps = view.get_sequence_gaps_for_author(AUTHOR).unwrap();
+        assert_eq!(gaps, vec![(3, 4), (7, 9)]);
+        assert!(view
+            .get_sequence_gaps_for_author("@nobody=.ed25519")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn forks_for_root_lists_forking_messages() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%reply=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "root": root, "branch": root}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%fork=.sha256",
+                    OTHER_AUTHOR,
+                    1,
+                    json!({"type": "post", "root": root, "fork": "%reply=.sha256"}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_forks_for_root(root).unwrap(),
+            vec![("%reply=.sha256".to_string(), "%fork=.sha256".to_string())]
+        );
+        assert!(view.get_forks_for_root("%reply=.sha256").unwrap().is_empty());
+    }
+
+    #[test]
+    fn hub_messages_count_distinct_authors() {
+        let mut view = make_test_view();
+        let votes = [
+            ("%a1=.sha256", AUTHOR, 1, "%hub=.sha256"),
+            ("%b1=.sha256", OTHER_AUTHOR, 1, "%hub=.sha256"),
+            ("%c1=.sha256", "@carol=.ed25519", 1, "%hub=.sha256"),
+            ("%a2=.sha256", AUTHOR, 2, "%hub=.sha256"),
+            ("%a3=.sha256", AUTHOR, 3, "%lonely=.sha256"),
+            ("%a4=.sha256", AUTHOR, 4, "%lonely=.sha256"),
+        ];
+        let items = votes
+            .iter()
+            .enumerate()
+            .map(|(i, &(key, author, sequence, link))| {
+                let content = json!({"type": "vote", "vote": {"link": link, "value": 1}});
+                (i as u64 + 1, message_bytes(key, author, sequence, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_hub_messages(2, 10).unwrap(),
+            vec![("%hub=.sha256".to_string(), 3)]
+        );
+        assert_eq!(view.get_hub_messages(1, 10).unwrap().len(), 2);
+        assert_eq!(view.get_hub_messages(1, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn causal_ancestors_follow_root_and_branch() {
+        let mut view = make_test_view();
+        let root = "%root=.sha256";
+        let items = vec![
+            (1, message_bytes(root, AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%reply1=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "post", "root": root, "branch": root}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%reply2=.sha256",
+                    AUTHOR,
+                    3,
+                    json!({"type": "post", "root": root, "branch": "%reply1=.sha256"}),
+                ),
+            ),
+            (
+                4,
+                message_bytes(
+                    "%reply3=.sha256",
+                    AUTHOR,
+                    4,
+                    json!({"type": "post", "root": root, "branch": ["%reply2=.sha256"]}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_causal_ancestors("%reply3=.sha256", 10).unwrap(),
+            vec!["%reply2=.sha256", root, "%reply1=.sha256"]
+        );
+        assert_eq!(
+            view.get_causal_ancestors("%reply3=.sha256", 1).unwrap(),
+            vec!["%reply2=.sha256", root]
+        );
+        assert!(view
+            .get_causal_ancestors("%reply3=.sha256", 0)
+            .unwrap()
+            .is_empty());
+        assert!(view.get_causal_ancestors(root, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn messages_referencing_author_include_contacts_and_mentions() {
+        let mut view = make_test_view();
+        let bob = "@bob=.ed25519";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%follow=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({"type": "contact", "contact": bob, "following": true}),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%mention=.sha256",
+                    OTHER_AUTHOR,
+                    1,
+                    json!({"type": "post", "mentions": [{"link": bob}]}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%both=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "contact", "contact": bob, "mentions": [bob]}),
+                ),
+            ),
+            (4, message_bytes("%other=.sha256", AUTHOR, 3, json!({"type": "post"}))),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_messages_referencing_author(bob).unwrap(),
+            vec!["%follow=.sha256", "%mention=.sha256", "%both=.sha256"]
+        );
+    }
+
+    #[test]
+    fn uri_contacts_are_read_in_classic_form() {
+        let mut view = make_test_view();
+        let other_uri = "ssb:feed/ed25519/vzoU7_XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc";
+        view.append_with_result(
+            1,
+            &message_bytes(
+                "%follow=.sha256",
+                AUTHOR,
+                1,
+                json!({"type": "contact", "contact": other_uri, "following": true}),
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            view.get_contacts_for_author(AUTHOR).unwrap(),
+            vec![(OTHER_AUTHOR.to_string(), true, false)]
+        );
+        assert_eq!(view.get_follows_for_author(AUTHOR).unwrap(), vec![OTHER_AUTHOR]);
+        assert_eq!(view.get_followers_for_author(OTHER_AUTHOR).unwrap(), vec![AUTHOR]);
+        assert_eq!(
+            view.get_social_graph().unwrap()[AUTHOR],
+            vec![(OTHER_AUTHOR.to_string(), true)]
+        );
+        assert_eq!(
+            view.get_messages_referencing_author(OTHER_AUTHOR).unwrap(),
+            vec!["%follow=.sha256"]
+        );
+    }
+
+    #[test]
+    fn hash_chain_detects_wrong_previous() {
+        let mut view = make_test_view();
+        let items = vec![
+            (1, chained_message_bytes("%first=.sha256", 1, None)),
+            (2, chained_message_bytes("%second=.sha256", 2, Some("%first=.sha256"))),
+            (3, chained_message_bytes("%third=.sha256", 3, Some("%second=.sha256"))),
+        ];
+        view.append_batch(&items).unwrap();
+        view.verify_hash_chain_for_author(AUTHOR).unwrap();
+
+        let forked = chained_message_bytes("%fourth=.sha256", 4, Some("%second=.sha256"));
+        view.append_with_result(4, &forked).unwrap();
+
+        let err = view.verify_hash_chain_for_author(AUTHOR).unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::BrokenHashChain { at_seq } => assert_eq!(at_seq, 4),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn feed_integrity_detects_gaps() {
+        let mut view = make_test_view();
+        let items = [1, 2, 4]
+            .iter()
+            .enumerate()
+            .map(|(i, sequence)| {
+                let key = format!("%msg{}=.sha256", sequence);
+                let content = json!({"type": "post"});
+                (i as u64 + 1, message_bytes(&key, AUTHOR, *sequence, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let err = view.check_feed_integrity(AUTHOR).unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::FeedGap {
+                author,
+                expected,
+                found,
+            } => {
+                assert_eq!(author, AUTHOR);
+                assert_eq!(expected, 3);
+                assert_eq!(found, 4);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        view.check_feed_integrity("@nobody=.ed25519").unwrap();
+    }
+
+    #[test]
+    fn append_batch_returns_sequences() {
+        let mut view = make_test_view();
+        let items = (1..=3)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                (i * 10, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            view.append_batch(&items).unwrap(),
+            vec![
+                AppendResult::Ok(10),
+                AppendResult::Ok(20),
+                AppendResult::Ok(30)
+            ]
+        );
+    }
+
+    #[test]
+    fn append_with_result_reports_errors() {
+        let mut view = make_test_view();
+
+        assert!(view.append_with_result(1, b"{\"key\": \"%broken").is_err());
+        view.append_with_result(
+            2,
+            &message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"})),
+        )
+        .unwrap();
+        assert_eq!(view.get_latest().unwrap(), 2);
+    }
+
+    #[test]
+    fn recent_messages_ordered_by_asserted_time() {
+        let mut view = make_test_view();
+        let items = (0..20)
+            .map(|i| {
+                let mut message: Value = from_slice(&message_bytes(
+                    &format!("%msg{}=.sha256", i),
+                    AUTHOR,
+                    i + 1,
+                    json!({"type": "post"}),
+                ))
+                .unwrap();
+                //Shuffle the asserted times so they don't follow the append order.
+                message["value"]["timestamp"] = json!(((i * 7) % 20) as f64 * 1000.0);
+                (u64::from(i) + 1, message.to_string().into_bytes())
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(
+            view.get_recent_messages(5).unwrap(),
+            vec![
+                "%msg17=.sha256",
+                "%msg14=.sha256",
+                "%msg11=.sha256",
+                "%msg8=.sha256",
+                "%msg5=.sha256"
+            ]
+        );
+    }
+
+    #[test]
+    fn append_batch_multi_feed_orders_each_feed() {
+        let mut view = make_test_view();
+        let post = json!({"type": "post"});
+        let items = vec![
+            (1, message_bytes("%a2=.sha256", AUTHOR, 2, post.clone())),
+            (2, message_bytes("%b1=.sha256", OTHER_AUTHOR, 1, post.clone())),
+            (3, message_bytes("%a1=.sha256", AUTHOR, 1, post.clone())),
+            (4, b"{\"key\": \"%broken".to_vec()),
+            (5, message_bytes("%b2=.sha256", OTHER_AUTHOR, 2, post.clone())),
+            (6, message_bytes("%a2fork=.sha256", AUTHOR, 2, post.clone())),
+        ];
+
+        assert_eq!(view.append_batch_multi_feed(items).unwrap(), vec![1, 2, 3, 5]);
+        assert_eq!(view.get_total_message_count().unwrap(), 4);
+        assert!(view.get_seq_by_key("%a2fork=.sha256").is_err());
+
+        let items = vec![
+            (7, message_bytes("%a1again=.sha256", AUTHOR, 1, post.clone())),
+            (8, message_bytes("%a3=.sha256", AUTHOR, 3, post)),
+        ];
+        assert_eq!(view.append_batch_multi_feed(items).unwrap(), vec![8]);
+        view.check_feed_integrity(AUTHOR).unwrap();
+    }
+
+    #[test]
+    fn append_and_get_seq_returns_the_stored_seq() {
+        let mut view = make_test_view();
+        let content = json!({"type": "post", "mentions": [{"link": "%other=.sha256"}]});
+        let bytes = message_bytes("%post=.sha256", AUTHOR, 1, content);
+
+        let seq = view.append_and_get_seq(7, &bytes).unwrap();
+
+        assert_eq!(seq, 7);
+        assert_eq!(view.get_seq_by_key("%post=.sha256").unwrap(), seq);
+
+        let second = message_bytes("%second=.sha256", AUTHOR, 2, json!({"type": "post"}));
+        assert_eq!(view.append_and_get_seq(12, &second).unwrap(), 12);
+
+        //Nothing is stored for a failed append, so there's no seq to return.
+        assert!(view.append_and_get_seq(13, &bytes).is_err());
+        assert_eq!(view.get_latest().unwrap(), 12);
+    }
+
+    #[test]
+    fn append_if_not_exists_skips_duplicates() {
+        let mut view = make_test_view();
+        let bytes = message_bytes("%post=.sha256", AUTHOR, 1, json!({"type": "post"}));
+
+        assert!(view.append_if_not_exists(1, &bytes).unwrap());
+        assert!(!view.append_if_not_exists(2, &bytes).unwrap());
+
+        assert_eq!(view.get_total_message_count().unwrap(), 1);
+        assert_eq!(view.get_seq_by_key("%post=.sha256").unwrap(), 1);
+        assert!(view
+            .append_if_not_exists(3, b"{\"key\": \"%broken")
+            .is_err());
+    }
+
+    #[test]
+    fn duplicate_key_is_an_error() {
+        let mut view = make_test_view();
+        let content = json!({"type": "post", "mentions": [{"link": "%other=.sha256"}]});
+        let bytes = message_bytes("%post=.sha256", AUTHOR, 1, content);
+
+        view.append_with_result(1, &bytes).unwrap();
+        let err = view.append_with_result(2, &bytes).unwrap_err();
+
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::DuplicateKey { key } => assert_eq!(key, "%post=.sha256"),
+            err => panic!("unexpected error {}", err),
+        }
+        assert_eq!(view.get_total_message_count().unwrap(), 1);
+        assert_eq!(view.get_links_from_message("%post=.sha256").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn duplicate_sequence_is_an_error() {
+        let mut view = make_test_view();
+        let first = message_bytes("%first=.sha256", AUTHOR, 1, json!({"type": "post"}));
+        let fork = message_bytes("%fork=.sha256", AUTHOR, 1, json!({"type": "post"}));
+
+        view.append_with_result(1, &first).unwrap();
+        let err = view.append_with_result(2, &fork).unwrap_err();
+
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::DuplicateSequence { author, seq } => {
+                assert_eq!(author, AUTHOR);
+                assert_eq!(seq, 1);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+        assert_eq!(view.get_total_message_count().unwrap(), 1);
+        assert!(view.get_seq_by_key("%fork=.sha256").is_err());
+    }
+
+    #[test]
+    fn unknown_encryption_is_an_error() {
+        let mut view = make_test_view();
+        let boxed = Value::String(format!("{}.box3", encode(&[7u8; 64][..])));
+
+        let err = view
+            .append_with_result(1, &message_bytes("%box3=.sha256", AUTHOR, 1, boxed))
+            .unwrap_err();
+
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::UnsupportedEncryption { suffix } => assert_eq!(suffix, "box3"),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[cfg(not(feature = "box2"))]
+    #[test]
+    fn box2_needs_the_box2_feature() {
+        let mut view = make_test_view();
+        let boxed = Value::String(format!("{}.box2", encode(&[7u8; 64][..])));
+
+        let err = view
+            .append_with_result(1, &message_bytes("%box2=.sha256", AUTHOR, 1, boxed))
+            .unwrap_err();
+
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::UnsupportedEncryption { suffix } => assert_eq!(suffix, "box2"),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[cfg(feature = "box2")]
+    #[test]
+    fn box2_messages_keep_their_raw_content() {
+        let (_, secret_key) = gen_keypair();
+        let mut view = FlumeViewSql::new_in_memory(vec![secret_key]).unwrap();
+        let ciphertext = format!("{}.box2", encode(&[7u8; 64][..]));
+
+        view.append_with_result(
+            1,
+            &message_bytes("%box2=.sha256", AUTHOR, 1, Value::String(ciphertext.clone())),
+        )
+        .unwrap();
+
+        assert_eq!(view.get_message_json("%box2=.sha256").unwrap(), None);
+        assert_eq!(
+            view.get_raw_content("%box2=.sha256").unwrap(),
+            Some(ciphertext)
+        );
+    }
+
+    #[test]
+    fn votes_for_message() {
+        let mut view = make_test_view();
+        let items = vec![1, 1, 1, -1]
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let key = format!("%vote{}=.sha256", i);
+                let content = json!({
+                    "type": "vote",
+                    "vote": {"link": "%target=.sha256", "value": value, "expression": "Like"}
+                });
+                (i as u64 + 1, message_bytes(&key, AUTHOR, i as u32 + 1, content))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+        let reply = json!({"type": "post", "root": "%target=.sha256"});
+        view.append(5, &message_bytes("%reply=.sha256", AUTHOR, 5, reply));
+
+        assert_eq!(view.get_vote_count_for_message("%target=.sha256").unwrap(), 4);
+
+        let breakdown = view.get_votes_breakdown_for_message("%target=.sha256").unwrap();
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[&1], 3);
+        assert_eq!(breakdown[&-1], 1);
+    }
+
+    #[test]
+    fn latest_about_for_author() {
+        let mut view = make_test_view();
+        let other = "@other=.ed25519";
+        let items = vec![
+            (
+                1,
+                message_bytes(
+                    "%about1=.sha256",
+                    AUTHOR,
+                    1,
+                    json!({"type": "about", "about": AUTHOR, "name": "piet"}),
+                ),
+            ),
+            (
+                2,
+                message_bytes(
+                    "%about2=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "about", "about": AUTHOR, "name": "pietgeursen", "description": "hi"}),
+                ),
+            ),
+            //Someone else naming us doesn't change our own profile.
+            (
+                3,
+                message_bytes(
+                    "%about3=.sha256",
+                    other,
+                    1,
+                    json!({"type": "about", "about": AUTHOR, "name": "not piet"}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let about = view.get_latest_about_for_author(AUTHOR).unwrap().unwrap();
+        assert_eq!(about["name"], "pietgeursen");
+        assert_eq!(about["description"], "hi");
+        assert_eq!(view.get_latest_about_for_author(other).unwrap(), None);
+    }
+
+    #[test]
+    fn iter_messages_by_type_reads_a_page_at_a_time() {
+        let mut view = make_test_view();
+        let items = (0..10_001)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                let content_type = if i == 10_000 { "vote" } else { "post" };
+                (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": content_type})))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let mut messages = view.iter_messages_by_type("post").unwrap();
+        for i in 0..10_000 {
+            let key = messages.next().unwrap().unwrap();
+            assert_eq!(key, format!("%msg{}=.sha256", i));
+            //Only the current page is held in memory, never the whole result.
+            assert!(messages.size_hint().0 < ITER_PAGE_SIZE as usize);
+        }
+
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn iter_messages_by_type_is_stable_across_writes() {
+        let db = TempDb::new("iter_messages_by_type_is_stable_across_writes");
+        let posts = |range: std::ops::RangeInclusive<u64>| {
+            range
+                .map(|i| {
+                    let key = format!("%post{}=.sha256", i);
+                    (i, message_bytes(&key, AUTHOR, i as u32, json!({"type": "post"})))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut view = FlumeViewSql::new(db.path(), Vec::new(), "").unwrap();
+        view.append_batch(&posts(1..=2500)).unwrap();
+        let mut writer = FlumeViewSql::new(db.path(), Vec::new(), "").unwrap();
+
+        let mut messages = view.iter_messages_by_type("post").unwrap();
+        let mut keys = vec![messages.next().unwrap().unwrap()];
+
+        //Another connection removes a key we've already seen and appends more while we iterate.
+        writer
+            .connection
+            .execute("DELETE FROM messages_raw WHERE flume_seq=1", NO_PARAMS)
             .unwrap();
+        writer.append_batch(&posts(2501..=2600)).unwrap();
 
-        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
-        let jsn = r#####"{
-  "key": "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256",
-  "value": {
-    "previous": "%xsMQA2GrsZew0GSxmDSBaoxDafVaUJ07YVaDGcp65a4=.sha256",
-    "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
-    "sequence": 4797,
-    "timestamp": 1543958997985,
-    "hash": "sha256",
-    "content": {
-      "type": "post",
-      "root": "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256",
-      "branch": "%sQV8QpyUNvh7fBAs2ts00Qo2gj44CQBmwonWJzm+AeM=.sha256",
-      "reply": {
-        "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256": "@+UMKhpbzXAII+2/7ZlsgkJwIsxdfeFi36Z5Rk1gCfY0=.ed25519",
-        "%sQV8QpyUNvh7fBAs2ts00Qo2gj44CQBmwonWJzm+AeM=.sha256": "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519"
-      },
-      "channel": null,
-      "recps": null,
-      "text": "If I understand correctly, cjdns overlaying over old IP (which is basically all of the cjdns uses so far) still requires old IP addresses to introduce you to the cjdns network, so the chicken and egg problem is still there.",
-      "mentions": []
-    },
-    "signature": "mi5j/buYZdsiH8l6CVWRqdBKe+0UG6tVTOoVVjMhYl38Nkmb8wiIEfe7zu0JWuiHkaAIq+0/ZqYr6aV14j4fAw==.sig.ed25519"
-  },
-  "timestamp": 1543959001933
-}
-"#####;
-        view.append(expected_seq, jsn.as_bytes());
-        let seq = view
-            .get_seq_by_key("%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256")
+        keys.extend(messages.map(|key| key.unwrap()));
+
+        let expected = (1..=2600)
+            .map(|i| format!("%post{}=.sha256", i))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn link_graph_for_author() {
+        let mut view = make_test_view();
+        let linking = |key: &str, sequence: u32, author: &str| {
+            let content = json!({
+                "type": "post",
+                "mentions": [{"link": "%a=.sha256"}, {"link": "%b=.sha256"}]
+            });
+            message_bytes(key, author, sequence, content)
+        };
+        let items = vec![
+            (1, linking("%first=.sha256", 1, AUTHOR)),
+            (2, linking("%second=.sha256", 2, AUTHOR)),
+            (3, linking("%someoneElse=.sha256", 1, "@other=.ed25519")),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let links = view.get_link_graph_for_author(AUTHOR).unwrap();
+        assert_eq!(links.len(), 4);
+        assert_eq!(
+            links[0],
+            ("%first=.sha256".to_string(), "%a=.sha256".to_string())
+        );
+        assert!(links.iter().all(|(from, _)| from != "%someoneElse=.sha256"));
+    }
+
+    #[test]
+    fn most_linked_messages() {
+        let mut view = make_test_view();
+        let mut items = vec![
+            (1, message_bytes("%quiet=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (2, message_bytes("%popular=.sha256", AUTHOR, 2, json!({"type": "post"}))),
+        ];
+        items.extend((3..=5).map(|i| {
+            let key = format!("%vote{}=.sha256", i);
+            let content = json!({"type": "vote", "vote": {"link": "%popular=.sha256", "value": 1}});
+            (i, message_bytes(&key, AUTHOR, i as u32, content))
+        }));
+        view.append_batch(&items).unwrap();
+
+        let messages = view.get_most_linked_messages(2).unwrap();
+        assert_eq!(
+            messages,
+            vec![("%popular=.sha256".to_string(), 3), ("%quiet=.sha256".to_string(), 0)]
+        );
+    }
+
+    #[test]
+    fn dangling_links() {
+        let mut view = make_test_view();
+        let target = "%target=.sha256";
+        let items = vec![
+            (1, message_bytes(target, AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                message_bytes(
+                    "%vote=.sha256",
+                    AUTHOR,
+                    2,
+                    json!({"type": "vote", "vote": {"link": target, "value": 1}}),
+                ),
+            ),
+            (
+                3,
+                message_bytes(
+                    "%mention=.sha256",
+                    AUTHOR,
+                    3,
+                    json!({"type": "post", "mentions": [{"link": "%missing=.sha256"}]}),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_dangling_links().unwrap(), vec!["%missing=.sha256"]);
+    }
+
+    #[test]
+    fn feed_queries_use_author_seq_index() {
+        let view = make_test_view();
+        let mut stmt = view
+            .connection
+            .prepare("EXPLAIN QUERY PLAN SELECT flume_seq FROM messages_raw WHERE author_id=?1 ORDER BY seq")
             .unwrap();
-        assert_eq!(seq, expected_seq as i64);
 
-        let seqs = view.get_seqs_by_type("post").unwrap();
-        assert_eq!(seqs[0], expected_seq as i64);
+        let plan = stmt
+            .query_map(&[&1], |row| row.get(3))
+            .unwrap()
+            .collect::<Result<Vec<String>, _>>()
+            .unwrap();
+
+        assert!(plan.iter().any(|detail| detail.contains("author_seq_index")));
+        assert!(!plan.iter().any(|detail| detail.contains("TEMP B-TREE")));
     }
 
     #[test]
-    fn test_db_integrity_ok() {
-        let filename = "/tmp/test_integrity.sqlite3";
-        let keys = Vec::new();
-        std::fs::remove_file(filename.clone())
-            .or::<Result<()>>(Ok(()))
+    fn content_type_queries_use_decrypted_content_type_index() {
+        let view = make_test_view();
+        let mut stmt = view
+            .connection
+            .prepare("EXPLAIN QUERY PLAN SELECT flume_seq FROM messages_raw WHERE content_type='post'")
             .unwrap();
 
-        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let plan = stmt
+            .query_map(NO_PARAMS, |row| row.get(3))
+            .unwrap()
+            .collect::<Result<Vec<String>, _>>()
+            .unwrap();
+
+        assert!(plan
+            .iter()
+            .any(|detail| detail.contains("decrypted_content_type_index")));
+    }
+
+    #[test]
+    fn stats_count_rows_and_size() {
+        let mut view = make_test_view();
+        let content = json!({"type": "post", "root": "%root=.sha256"});
+        view.append(1, &message_bytes("%reply=.sha256", AUTHOR, 1, content));
+
+        let stats = view.get_stats().unwrap();
+        assert!(stats.message_count >= 1);
+        assert!(stats.author_count >= 1);
+        assert!(stats.link_count >= 1);
+
+        let page_size: i64 = view
+            .connection
+            .query_row("PRAGMA page_size", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert!(stats.db_size_bytes > 0);
+        assert_eq!(stats.db_size_bytes % page_size as u64, 0);
+    }
+
+    #[test]
+    fn messages_by_time_range() {
+        let mut view = make_test_view();
+        let items = (1..=5)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                (i, timestamped_message_bytes(&key, i as u32, i as f64 * 100.0))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        let keys = view.get_messages_by_time_range(200.0, 400.0, 10).unwrap();
+        assert_eq!(keys, vec!["%msg2=.sha256", "%msg3=.sha256", "%msg4=.sha256"]);
+
+        let err = view.get_messages_by_time_range(400.0, 200.0, 10).unwrap_err();
+        match err.downcast::<FlumeViewSqlError>().unwrap() {
+            FlumeViewSqlError::InvalidTimeRange { .. } => (),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn message_count_in_time_range() {
+        let mut view = make_test_view();
+        let items = (1..=5)
+            .map(|i| {
+                let key = format!("%msg{}=.sha256", i);
+                (i, timestamped_message_bytes(&key, i as u32, i as f64 * 100.0))
+            })
+            .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_message_count_in_time_range(600.0, 900.0).unwrap(), 0);
+        assert_eq!(view.get_message_count_in_time_range(200.0, 400.0).unwrap(), 3);
+        assert_eq!(view.get_message_count_in_time_range(0.0, 1000.0).unwrap(), 5);
+    }
+
+    #[test]
+    fn oldest_and_newest_message_by_asserted_time() {
+        let mut view = make_test_view();
+        assert_eq!(view.get_oldest_message().unwrap(), None);
+        assert_eq!(view.get_newest_message().unwrap(), None);
+
+        //Appended out of timestamp order, so flume seq doesn't give the right answer.
+        let items = [
+            ("%middle=.sha256", 2000.0),
+            ("%newest=.sha256", 3000.0),
+            ("%oldest=.sha256", 1000.0),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (key, timestamp))| {
+            let bytes = json!({
+                "key": key,
+                "value": {
+                    "author": AUTHOR,
+                    "sequence": i + 1,
+                    "timestamp": timestamp,
+                    "content": {"type": "post"}
+                },
+                "timestamp": 5000.0
+            })
+            .to_string()
+            .into_bytes();
+            (i as u64 + 1, bytes)
+        })
+        .collect::<Vec<_>>();
+        view.append_batch(&items).unwrap();
+
+        assert_eq!(view.get_oldest_message().unwrap().unwrap(), "%oldest=.sha256");
+        assert_eq!(view.get_newest_message().unwrap().unwrap(), "%newest=.sha256");
+    }
+
+    #[test]
+    fn warm_statement_cache_compiles_against_schema() {
+        let mut view = make_test_view();
+        view.warm_statement_cache().unwrap();
+
+        //Warming twice only hits the cache.
+        view.warm_statement_cache().unwrap();
+
+        let content = json!({"type": "post", "root": "%root=.sha256"});
+        view.append(1, &message_bytes("%reply=.sha256", AUTHOR, 1, content));
+        assert_eq!(view.get_latest().unwrap(), 1);
+        assert!(view.get_message_json("%reply=.sha256").unwrap().is_some());
+    }
+
+    #[test]
+    fn append_skips_malformed_json() {
+        let mut view = make_test_view();
+        view.append(1, b"{\"key\": \"%broken");
+
+        assert_eq!(view.get_latest().unwrap(), 0);
+    }
+
+    #[test]
+    fn attempt_decryption_tries_every_key() {
+        private_box::init();
+        let (_, first_secret_key) = gen_keypair();
+        let (second_public_key, second_secret_key) = gen_keypair();
+
+        let content = json!({"type": "post", "text": "only for my second key"});
+        let cypher = private_box::encrypt(content.to_string().as_bytes(), &[second_public_key]);
+
+        let message: SsbMessage = from_value(json!({
+            "key": "%privateMessageKey=.sha256",
+            "value": {
+                "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+                "sequence": 1,
+                "timestamp": 1543958997985.0,
+                "content": format!("{}.box", encode(&cypher))
+            },
+            "timestamp": 1543959001933.0
+        }))
+        .unwrap();
+
+        let keys = vec![first_secret_key, second_secret_key];
+        let (decryption_result, message) = attempt_decryption(message, &keys).unwrap();
+
+        assert_eq!(decryption_result, DecryptionResult::Decrypted);
+        assert_eq!(message.value.content["text"], "only for my second key");
+    }
+
+    #[test]
+    fn decryption_failures_have_reasons() {
+        private_box::init();
+        let (public_key, secret_key) = gen_keypair();
+        let (other_public_key, _) = gen_keypair();
+        let mut view = FlumeViewSql::new_in_memory(vec![secret_key]).unwrap();
+
+        let not_json = private_box::encrypt(b"not json", &[public_key]);
+        let items = vec![
+            (1, message_bytes("%public=.sha256", AUTHOR, 1, json!({"type": "post"}))),
+            (
+                2,
+                private_message_bytes("%mine=.sha256", 2, json!({"type": "post"}), &public_key),
+            ),
+            (
+                3,
+                private_message_bytes(
+                    "%theirs=.sha256",
+                    3,
+                    json!({"type": "post"}),
+                    &other_public_key,
+                ),
+            ),
+            (
+                4,
+                message_bytes("%corrupt=.sha256", AUTHOR, 4, json!("not base64!.box")),
+            ),
+            (
+                5,
+                message_bytes(
+                    "%notJson=.sha256",
+                    AUTHOR,
+                    5,
+                    json!(format!("{}.box", encode(&not_json))),
+                ),
+            ),
+        ];
+        view.append_batch(&items).unwrap();
+
+        let failures = view.get_decryption_failures().unwrap();
+        assert_eq!(
+            failures,
+            vec![
+                (
+                    "%theirs=.sha256".to_string(),
+                    DecryptionResult::FailedNoMatchingKey.as_str().to_string()
+                ),
+                (
+                    "%corrupt=.sha256".to_string(),
+                    DecryptionResult::FailedInvalidCiphertext.as_str().to_string()
+                ),
+                (
+                    "%notJson=.sha256".to_string(),
+                    DecryptionResult::FailedInvalidJson.as_str().to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_db_integrity_ok() {
+        let mut view = make_test_view();
         view.check_db_integrity().unwrap();
+        assert!(view.check_integrity_detailed().unwrap().is_empty());
     }
     #[test]
     fn test_db_integrity_fails() {
-        let filename = "/tmp/test_integrity_bad.sqlite3";
-        let keys = Vec::new();
-        std::fs::remove_file(filename.clone())
-            .or::<Result<()>>(Ok(()))
-            .unwrap();
+        let db = TempDb::new("test_db_integrity_fails");
+        let filename = db.path();
 
-        let mut view = FlumeViewSql::new(filename.clone(), keys, "").unwrap();
+        //In WAL mode sqlite could keep reading pages from the wal file, so corrupt a plain db.
+        let mut view = FlumeViewSqlBuilder::new()
+            .path(filename)
+            .journal_mode("DELETE")
+            .build()
+            .unwrap();
 
         std::fs::write(filename, b"BANG").unwrap();
 
@@ -425,5 +5302,6 @@ mod test {
             Ok(_) => panic!(),
             Err(_) => assert!(true),
         }
+        assert!(view.check_integrity_detailed().is_err());
     }
 }
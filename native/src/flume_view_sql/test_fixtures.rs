@@ -0,0 +1,198 @@
+use base64::encode;
+use serde_json::{json, Value};
+use sodiumoxide::crypto::box_::PublicKey;
+
+use flume_view_sql::*;
+
+pub const AUTHOR: &str = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+pub const OTHER_AUTHOR: &str = "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519";
+
+pub const FIXTURE_POST: &str = r#####"{
+  "key": "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256",
+  "value": {
+    "previous": "%xsMQA2GrsZew0GSxmDSBaoxDafVaUJ07YVaDGcp65a4=.sha256",
+    "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+    "sequence": 4797,
+    "timestamp": 1543958997985,
+    "hash": "sha256",
+    "content": {
+      "type": "post",
+      "root": "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256",
+      "branch": "%sQV8QpyUNvh7fBAs2ts00Qo2gj44CQBmwonWJzm+AeM=.sha256",
+      "reply": {
+        "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256": "@+UMKhpbzXAII+2/7ZlsgkJwIsxdfeFi36Z5Rk1gCfY0=.ed25519",
+        "%sQV8QpyUNvh7fBAs2ts00Qo2gj44CQBmwonWJzm+AeM=.sha256": "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519"
+      },
+      "channel": null,
+      "recps": null,
+      "text": "If I understand correctly, cjdns overlaying over old IP (which is basically all of the cjdns uses so far) still requires old IP addresses to introduce you to the cjdns network, so the chicken and egg problem is still there.",
+      "mentions": []
+    },
+    "signature": "mi5j/buYZdsiH8l6CVWRqdBKe+0UG6tVTOoVVjMhYl38Nkmb8wiIEfe7zu0JWuiHkaAIq+0/ZqYr6aV14j4fAw==.sig.ed25519"
+  },
+  "timestamp": 1543959001933
+}
+"#####;
+
+//OTHER_AUTHOR follows AUTHOR.
+pub const FIXTURE_CONTACT: &str = r#####"{
+  "key": "%IXFZNn/nqMo0K25MAsuik2uM8Ji1MYIXZpsTkbfJZTw=.sha256",
+  "value": {
+    "previous": null,
+    "author": "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519",
+    "sequence": 1,
+    "timestamp": 1543959010000,
+    "hash": "sha256",
+    "content": {
+      "type": "contact",
+      "contact": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+      "following": true
+    },
+    "signature": "285VTmujmtwt1fXKygkpIkOOdi46HiC7XjB4BlAjAfYguRolik/UlEndVBF2A0C/qycMALnYWq0stoxNyo99/k==.sig.ed25519"
+  },
+  "timestamp": 1543959011000
+}
+"#####;
+
+//OTHER_AUTHOR likes FIXTURE_POST.
+pub const FIXTURE_VOTE: &str = r#####"{
+  "key": "%285VTmujmtwt1fXKygkpIkOOdi46HiC7XjB4BlAjAfY=.sha256",
+  "value": {
+    "previous": "%IXFZNn/nqMo0K25MAsuik2uM8Ji1MYIXZpsTkbfJZTw=.sha256",
+    "author": "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519",
+    "sequence": 2,
+    "timestamp": 1543959020000,
+    "hash": "sha256",
+    "content": {
+      "type": "vote",
+      "vote": {
+        "link": "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256",
+        "value": 1,
+        "expression": "Like"
+      }
+    },
+    "signature": "FS9jePYdKX0HueUc9ZGzT/rmzH0Iaqb3tSvT1ZVSx+UIXFZNn/nqMo0K25MAsuik2uM8Ji1MYIXZpsTkbfJZTw==.sig.ed25519"
+  },
+  "timestamp": 1543959021000
+}
+"#####;
+
+//A private message from OTHER_AUTHOR that none of the test keys can open.
+pub const FIXTURE_ENCRYPTED: &str = r#####"{
+  "key": "%guRolik/UlEndVBF2A0C/qycMALnYWq0stoxNyo99/k=.sha256",
+  "value": {
+    "previous": "%285VTmujmtwt1fXKygkpIkOOdi46HiC7XjB4BlAjAfY=.sha256",
+    "author": "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519",
+    "sequence": 3,
+    "timestamp": 1543959030000,
+    "hash": "sha256",
+    "content": "lK0AeDd2kgtkwkZsRg+7vQnqOAY0LqVLsAImhwSgx3A7vbpqo0tUkqy8CAULmmZwWCZxkb20A3FRlSdnQgRvDdlWBDwQZenwTYeEB9vuPlGRt5bE3SgeLteV2PwLYyfqfXoLBbHfebveNxIaC0gc5ir9BxJRGEXe.box",
+    "signature": "guRolik/UlEndVBF2A0C/qycMALnYWq0stoxNyo99/kFS9jePYdKX0HueUc9ZGzT/rmzH0Iaqb3tSvT1ZVSx+U==.sig.ed25519"
+  },
+  "timestamp": 1543959031000
+}
+"#####;
+
+/// An empty in-memory view with no secret keys.
+pub fn make_test_view() -> FlumeViewSql {
+    FlumeViewSql::new_in_memory(Vec::new()).unwrap()
+}
+
+pub fn message_bytes(key: &str, author: &str, sequence: u32, content: Value) -> Vec<u8> {
+    json!({
+        "key": key,
+        "value": {
+            "author": author,
+            "sequence": sequence,
+            "timestamp": 1543958997985.0 + sequence as f64,
+            "content": content
+        },
+        "timestamp": 1543959001933.0 + sequence as f64
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// A post by `AUTHOR` whose previous is set, for hash chain tests.
+pub fn chained_message_bytes(key: &str, sequence: u32, previous: Option<&str>) -> Vec<u8> {
+    json!({
+        "key": key,
+        "value": {
+            "previous": previous,
+            "author": AUTHOR,
+            "sequence": sequence,
+            "timestamp": 1543958997985.0 + sequence as f64,
+            "content": {"type": "post"}
+        },
+        "timestamp": 1543959001933.0 + sequence as f64
+    })
+    .to_string()
+    .into_bytes()
+}
+
+pub fn timestamped_message_bytes(key: &str, sequence: u32, timestamp: f64) -> Vec<u8> {
+    json!({
+        "key": key,
+        "value": {
+            "author": AUTHOR,
+            "sequence": sequence,
+            "timestamp": timestamp,
+            "content": {"type": "post"}
+        },
+        "timestamp": timestamp
+    })
+    .to_string()
+    .into_bytes()
+}
+
+pub fn private_message_bytes(
+    key: &str,
+    sequence: u32,
+    content: Value,
+    recipient: &PublicKey,
+) -> Vec<u8> {
+    private_box::init();
+    let cypher = private_box::encrypt(content.to_string().as_bytes(), &[*recipient]);
+    let boxed = Value::String(format!("{}.box", encode(&cypher)));
+
+    message_bytes(key, AUTHOR, sequence, boxed)
+}
+
+//A db file only one test uses, removed along with its wal, shm and journal files when dropped.
+//The pid keeps concurrent test runs apart, the name keeps tests within one run apart.
+pub struct TempDb {
+    path: String,
+}
+
+impl TempDb {
+    pub fn new(name: &str) -> TempDb {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "flumeview_sql_{}_{}.sqlite3",
+                std::process::id(),
+                name
+            ))
+            .to_string_lossy()
+            .into_owned();
+
+        let db = TempDb { path };
+        db.remove_files();
+        db
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn remove_files(&self) {
+        for suffix in &["", "-wal", "-shm", "-journal"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.path, suffix));
+        }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
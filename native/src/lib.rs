@@ -13,10 +13,17 @@ extern crate serde_derive;
 extern crate serde_json;
 
 extern crate base64;
+extern crate bloom;
 extern crate flumedb;
+#[cfg(feature = "async")]
+extern crate futures;
 extern crate node_napi;
 extern crate private_box;
 extern crate rusqlite;
+#[cfg(test)]
+extern crate sodiumoxide;
+#[cfg(feature = "async")]
+extern crate tokio;
 
 use failure::Error;
 
@@ -34,7 +41,12 @@ use flumedb::Sequence;
 use private_box::SecretKey;
 
 pub mod flume_view_sql;
-pub use flume_view_sql::FlumeViewSql;
+pub mod ssb_types;
+pub use flume_view_sql::{
+    AutoVacuumMode, FlumeViewSql, FlumeViewSqlBuilder, FlumeViewSqlExt, SynchronousMode,
+    TempStoreMode,
+};
+pub use ssb_types::{SsbMessage, SsbValue};
 
 struct SsbQuery {
     view: FlumeViewSql,
@@ -57,7 +69,7 @@ impl SsbQuery {
         self.view.get_latest().unwrap()
     }
 
-    fn process(&mut self, num_items: i64) {
+    fn process(&mut self, num_items: i64) -> Result<(), Error> {
         let latest = self.get_latest();
 
         //If the latest is 0, we haven't got anything in the db. Don't skip the very first
@@ -80,9 +92,7 @@ impl SsbQuery {
             .map(|data| (data.id + latest, data.data_buffer)) //TODO log_latest might not be the right thing
             .chunks(1000)
             .into_iter()
-            .for_each(|chunk| {
-                self.view.append_batch(&chunk.collect_vec());
-            })
+            .try_for_each(|chunk| self.view.append_batch(&chunk.collect_vec()).map(|_| ()))
     }
 }
 
@@ -111,7 +121,9 @@ extern "C" fn process(env: napi_env, info: napi_callback_info) -> napi_value {
     unsafe { napi_unwrap(env, this, &mut ptr_ssb_query) };
 
     let ssb_query = ptr_ssb_query as *mut SsbQuery;
-    unsafe { (*ssb_query).process(num) };
+    if let Err(err) = unsafe { (*ssb_query).process(num) } {
+        throw_error(env, err);
+    }
 
     get_undefined_value(env)
 }
@@ -0,0 +1,23 @@
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SsbValue {
+    pub previous: Option<String>,
+    pub author: String,
+    pub sequence: u32,
+    pub timestamp: f64,
+    pub content: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SsbMessage {
+    pub key: String,
+    pub value: SsbValue,
+    pub timestamp: f64,
+}
+
+impl SsbMessage {
+    pub fn from_bytes(data: &[u8]) -> Result<SsbMessage, serde_json::Error> {
+        serde_json::from_slice(data)
+    }
+}